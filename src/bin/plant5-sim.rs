@@ -0,0 +1,4 @@
+// Headless plant simulation. Does not require the "render" feature.
+fn main() {
+    std::process::exit(plant5::headless::main(std::env::args().skip(1)));
+}
@@ -0,0 +1,560 @@
+// Plant definition files: the rules of a plant plus the graph it starts out as.
+use crate::rgg::fingerprint::Fingerprint;
+use crate::rgg::rgg_graph::Fnv;
+use crate::rgg::{
+    DerivationMode, EdgeLabel, Environment, Node, Phase, RggGraph, Rule, RuleGroup, RuleProblem,
+    Value,
+};
+use anyhow::{anyhow, bail, Context};
+use gamma::graph::{AppendableGraph, Graph};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+/// Descriptive information about a plant definition. Not used by the simulation.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Metadata {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+}
+
+/// A node of the starting graph.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AxiomNode {
+    /// Identify the node within the definition file. Does not have to match the id in the RggGraph.
+    pub id: usize,
+    pub name: String,
+    #[serde(default)]
+    pub values: BTreeMap<String, Value>,
+    /// The immediate predecessor of this node. Implies an edge between the two.
+    #[serde(default)]
+    pub parent: Option<usize>,
+}
+
+/// An edge of the starting graph, written as `[from, to]`, `[from, to, label]`,
+/// `[from, to, label, values]` or as a map.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AxiomEdge {
+    pub from: usize,
+    pub to: usize,
+    #[serde(default)]
+    pub label: EdgeLabel,
+    #[serde(default)]
+    pub values: BTreeMap<String, Value>,
+}
+
+/// The graph a plant starts out as.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AxiomGraph {
+    pub nodes: Vec<AxiomNode>,
+    #[serde(default)]
+    pub edges: Vec<AxiomEdge>,
+}
+
+impl AxiomGraph {
+    /// Create a RggGraph containing the axiom's nodes and edges.
+    pub fn build(&self) -> anyhow::Result<RggGraph> {
+        let mut graph = RggGraph::new();
+        // Map definition ids to graph ids
+        let mut ids = HashMap::new();
+        for node in &self.nodes {
+            let id = graph.insert_node_with(Node {
+                name: node.name.clone(),
+                values: node.values.clone(),
+            });
+            if ids.insert(node.id, id).is_some() {
+                bail!("Duplicate axiom node id {}", node.id);
+            }
+        }
+
+        let lookup = |id: usize| {
+            ids.get(&id)
+                .copied()
+                .ok_or_else(|| anyhow!("Axiom refers to unknown node id {}", id))
+        };
+        for node in &self.nodes {
+            if let Some(parent) = node.parent {
+                let (me, parent) = (lookup(node.id)?, lookup(parent)?);
+                if !graph.graph.has_edge(me, parent)? {
+                    graph.graph.add_edge(parent, me)?;
+                }
+                graph.graph.add_ancestor(me, parent);
+            }
+        }
+        for edge in &self.edges {
+            let (from, to) = (lookup(edge.from)?, lookup(edge.to)?);
+            graph.graph.add_labeled_edge(from, to, edge.label)?;
+            graph.graph.set_edge_values(from, to, edge.values.clone());
+        }
+
+        Ok(graph)
+    }
+}
+
+impl Fingerprint for AxiomGraph {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write_usize(self.nodes.len());
+        for node in &self.nodes {
+            node.id.hash_into(hash);
+            node.name.hash_into(hash);
+            node.values.hash_into(hash);
+            node.parent.hash_into(hash);
+        }
+        hash.write_usize(self.edges.len());
+        for edge in &self.edges {
+            edge.from.hash_into(hash);
+            edge.to.hash_into(hash);
+            edge.label.hash_into(hash);
+            edge.values.hash_into(hash);
+        }
+    }
+}
+
+/// Everything required to grow a plant.
+#[derive(Clone, Deserialize)]
+pub struct PlantDefinition {
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// Shorthand for a single phase with a single group that applies all rules once.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Named phases that are run in order in every step.
+    #[serde(default)]
+    pub phases: Vec<Phase>,
+    /// How the rules are applied in each step.
+    #[serde(default)]
+    pub derivation: DerivationMode,
+    pub axiom: AxiomGraph,
+    /// Seed for all random decisions. A random seed is picked if there is none.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Named numbers that expressions and conditions can refer to, so they can be tuned in one place.
+    #[serde(default)]
+    pub parameters: BTreeMap<String, f64>,
+}
+
+impl PlantDefinition {
+    /// The phases to run in every step, with `rules` turned into a phase of its own.
+    pub fn phases(&self) -> anyhow::Result<Vec<Phase>> {
+        match (self.rules.is_empty(), self.phases.is_empty()) {
+            (false, false) => bail!("A plant definition can have either rules or phases, not both"),
+            (_, true) => Ok(vec![Phase {
+                name: "main".to_string(),
+                groups: vec![RuleGroup::new(self.rules.clone())],
+            }]),
+            (true, false) => Ok(self.phases.clone()),
+        }
+    }
+
+    /// A hash of everything that decides how the plant grows, except for the seed.
+    /// Identifies the definition that the history of a plant was recorded with, however it is
+    /// written down.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = Fnv::new();
+        self.rules.hash_into(&mut hash);
+        self.phases.hash_into(&mut hash);
+        self.derivation.hash_into(&mut hash);
+        self.axiom.hash_into(&mut hash);
+        self.parameters.hash_into(&mut hash);
+        hash.finish()
+    }
+
+    /// Find mistakes in the rules without running them. Problems are located by their path
+    /// from the root of the definition, e.g. `phases.0.groups.1.rules.2.to.0.merge`.
+    pub fn validate(&self) -> Vec<RuleProblem> {
+        let env = Environment {
+            generation: 0,
+            parameters: self.parameters.clone(),
+        };
+        let mut problems = vec![];
+        if !self.rules.is_empty() && !self.phases.is_empty() {
+            problems.push(RuleProblem {
+                path: vec!["phases".to_string()],
+                message: "A plant definition can have either rules or phases, not both".to_string(),
+            });
+        }
+        let mut validate_rules = |rules: &[Rule], prefix: Vec<String>| {
+            for (index, rule) in rules.iter().enumerate() {
+                let mut prefix = prefix.clone();
+                prefix.push(index.to_string());
+                problems.extend(
+                    rule.validate(&env)
+                        .into_iter()
+                        .map(|problem| problem.within(&prefix)),
+                );
+            }
+        };
+        validate_rules(&self.rules, vec!["rules".to_string()]);
+        for (phase_index, phase) in self.phases.iter().enumerate() {
+            for (group_index, group) in phase.groups.iter().enumerate() {
+                let prefix = vec![
+                    "phases".to_string(),
+                    phase_index.to_string(),
+                    "groups".to_string(),
+                    group_index.to_string(),
+                    "rules".to_string(),
+                ];
+                validate_rules(&group.rules, prefix);
+            }
+        }
+        problems
+    }
+
+    /// Read a plant definition from YAML. `path` is only used to describe where errors are.
+    pub fn parse<P: AsRef<Path>>(contents: &str, path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        serde_yaml::from_str(contents).map_err(|e| {
+            let location = match e.location() {
+                Some(location) => format!(
+                    "{}:{}:{}",
+                    path.display(),
+                    location.line(),
+                    location.column()
+                ),
+                None => path.display().to_string(),
+            };
+            anyhow::Error::new(e).context(format!("Invalid plant definition {}", location))
+        })
+    }
+
+    /// Read a plant definition and find the mistakes in its rules, each described as
+    /// `file:line:column: path: message`. `path` is only used to describe where the mistakes are.
+    pub fn check(contents: &str, path: &Path) -> anyhow::Result<(Self, Vec<String>)> {
+        let definition = Self::parse(contents, path)?;
+        let problems = definition.validate();
+        if problems.is_empty() {
+            return Ok((definition, vec![]));
+        }
+        let source_map = SourceMap::new(contents)?;
+        let problems = problems
+            .iter()
+            .map(|problem| source_map.describe(path, problem))
+            .collect();
+        Ok((definition, problems))
+    }
+
+    /// Read a plant definition from a YAML file. Fails if its rules have mistakes.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read plant definition {}", path.display()))?;
+        let (definition, problems) = Self::check(&contents, path)?;
+        if !problems.is_empty() {
+            bail!(
+                "Invalid plant definition {}:\n{}",
+                path.display(),
+                problems.join("\n")
+            );
+        }
+        Ok(definition)
+    }
+}
+
+/// Where the values of a YAML document are, by the keys and indices that lead to them.
+pub struct SourceMap {
+    /// Line and column of every value. Values in mappings are located at their key.
+    locations: HashMap<Vec<String>, (usize, usize)>,
+    /// The mappings and sequences that are being read.
+    stack: Vec<Frame>,
+}
+
+enum Frame {
+    /// The key of the value being read, if it was read already.
+    /// Mappings are located at their first key, since that is where block mappings start.
+    Mapping { key: Option<String>, located: bool },
+    /// The index of the value being read.
+    Sequence(usize),
+}
+
+impl SourceMap {
+    pub fn new(source: &str) -> anyhow::Result<Self> {
+        let mut map = Self {
+            locations: HashMap::new(),
+            stack: vec![],
+        };
+        Parser::new(source.chars()).load(&mut map, false)?;
+        Ok(map)
+    }
+
+    /// The line and column of the value at `path`. If the path leads into a value that is
+    /// written in a short form, such as `merge: [0, 1, 0]`, that value is located instead.
+    pub fn locate(&self, path: &[String]) -> Option<(usize, usize)> {
+        (0..=path.len())
+            .rev()
+            .find_map(|len| self.locations.get(&path[..len]).copied())
+    }
+
+    /// Describe a problem as `file:line:column: path: message`.
+    pub fn describe(&self, file: &Path, problem: &RuleProblem) -> String {
+        match self.locate(&problem.path) {
+            Some((line, column)) => format!("{}:{}:{}: {}", file.display(), line, column, problem),
+            None => format!("{}: {}", file.display(), problem),
+        }
+    }
+
+    fn path(&self) -> Vec<String> {
+        self.stack
+            .iter()
+            .map(|frame| match frame {
+                Frame::Mapping { key, .. } => key.clone().unwrap_or_default(),
+                Frame::Sequence(index) => index.to_string(),
+            })
+            .collect()
+    }
+
+    fn insert(&mut self, path: Vec<String>, mark: Marker) {
+        self.locations.insert(path, (mark.line(), mark.col()));
+    }
+
+    fn begin_value(&mut self, mark: Marker) {
+        match self.stack.last() {
+            Some(Frame::Mapping { .. }) => {}
+            None | Some(Frame::Sequence(_)) => self.insert(self.path(), mark),
+        }
+    }
+
+    fn end_value(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping { key, .. }) => *key = None,
+            Some(Frame::Sequence(index)) => *index += 1,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for SourceMap {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, ..) => match self.stack.last_mut() {
+                Some(Frame::Mapping {
+                    key: key @ None,
+                    located,
+                }) => {
+                    *key = Some(value);
+                    let first_key = !std::mem::replace(located, true);
+                    let path = self.path();
+                    if first_key {
+                        self.insert(path[..path.len() - 1].to_vec(), mark);
+                    }
+                    self.insert(path, mark);
+                }
+                _ => {
+                    self.begin_value(mark);
+                    self.end_value();
+                }
+            },
+            Event::Alias(_) => {
+                self.begin_value(mark);
+                self.end_value();
+            }
+            Event::MappingStart(_) => {
+                let located = matches!(self.stack.last(), Some(Frame::Mapping { .. }));
+                self.stack.push(Frame::Mapping { key: None, located });
+            }
+            Event::SequenceStart(_) => {
+                self.begin_value(mark);
+                self.stack.push(Frame::Sequence(0));
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.end_value();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFINITION: &str = r#"
+metadata:
+  name: Test plant
+rules:
+  - from:
+      nodes:
+        - {id: 0, name: "stem"}
+    to:
+      - delete: 0
+axiom:
+  nodes:
+    - {id: 10, name: "stem", values: {dir: 0}}
+    - {id: 20, name: "stem", parent: 10}
+    - {id: 30, name: "shoot", parent: 20}
+  edges:
+    - [10, 30]
+    - [20, 30, branch, {angle: 30}]
+"#;
+
+    #[test]
+    fn test_de_definition() {
+        let definition: PlantDefinition = serde_yaml::from_str(DEFINITION).unwrap();
+        assert_eq!(definition.metadata.name, Some("Test plant".to_string()));
+        assert_eq!(definition.rules.len(), 1);
+        assert_eq!(definition.axiom.nodes.len(), 3);
+        assert_eq!(definition.axiom.nodes[1].parent, Some(10));
+        assert_eq!(definition.derivation, DerivationMode::Sequential);
+        assert!(definition.parameters.is_empty());
+
+        let definition: PlantDefinition = serde_yaml::from_str(&format!(
+            "{}\nderivation: {{parallel: priority}}",
+            DEFINITION
+        ))
+        .unwrap();
+        assert_eq!(
+            definition.derivation,
+            DerivationMode::Parallel(crate::rgg::ConflictPolicy::Priority)
+        );
+
+        let definition: PlantDefinition = serde_yaml::from_str(&format!(
+            "{}\nparameters: {{angle: 137.5, rate: 2}}",
+            DEFINITION
+        ))
+        .unwrap();
+        assert_eq!(definition.parameters["angle"], 137.5);
+        assert_eq!(definition.parameters["rate"], 2.0);
+    }
+
+    #[test]
+    fn test_de_phases() {
+        let definition: PlantDefinition = serde_yaml::from_str(
+            r#"
+phases:
+  - name: grow
+    groups:
+      - mode: ordered
+        repeat: fixpoint
+        max_iterations: 10
+        rules:
+          - from: {nodes: [{id: 0, name: "stem"}]}
+            to: []
+      - rules: []
+  - name: prune
+    groups: []
+axiom:
+  nodes: []"#,
+        )
+        .unwrap();
+        let phases = definition.phases().unwrap();
+        assert_eq!(phases.len(), 2);
+        assert_eq!(phases[0].name, "grow");
+        assert_eq!(phases[0].groups[0].max_iterations, 10);
+        assert_eq!(phases[0].groups[0].rules.len(), 1);
+
+        // Rules are shorthand for a single phase
+        let definition: PlantDefinition = serde_yaml::from_str(DEFINITION).unwrap();
+        let phases = definition.phases().unwrap();
+        assert_eq!(phases.len(), 1);
+        assert_eq!(phases[0].groups[0].rules.len(), 1);
+
+        let definition: PlantDefinition = serde_yaml::from_str(&format!(
+            "{}\nphases: [{{name: grow, groups: []}}]",
+            DEFINITION
+        ))
+        .unwrap();
+        assert!(definition.phases().is_err());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let fingerprint = |contents: &str| {
+            serde_yaml::from_str::<PlantDefinition>(contents)
+                .unwrap()
+                .fingerprint()
+        };
+        let original = fingerprint(DEFINITION);
+        assert_eq!(
+            fingerprint(&format!("# A comment\n{}\n\n", DEFINITION)),
+            original
+        );
+        // Neither metadata nor the order of keys matter
+        assert_eq!(
+            fingerprint(
+                &DEFINITION
+                    .replace("Test plant", "Renamed plant")
+                    .replace("{id: 0, name: \"stem\"}", "{name: stem, id: 0}")
+            ),
+            original
+        );
+        assert_ne!(
+            fingerprint(&DEFINITION.replace("delete: 0", "delete: 1")),
+            original
+        );
+        assert_ne!(
+            fingerprint(&format!("{}\nparameters: {{growth: 1}}", DEFINITION)),
+            original
+        );
+    }
+
+    #[test]
+    fn test_build_axiom() {
+        let definition: PlantDefinition = serde_yaml::from_str(DEFINITION).unwrap();
+        let graph = definition.axiom.build().unwrap();
+        assert_eq!(graph.order(), 3);
+        assert_eq!(graph.graph.size(), 3);
+        assert_eq!(graph.graph.edge_label(0, 1), Some(EdgeLabel::Plain));
+        assert!(graph.graph.has_labeled_edge(1, 2, EdgeLabel::Branch));
+        assert_eq!(
+            graph.graph.edge_values(1, 2).unwrap()["angle"],
+            Value::Int(30)
+        );
+        assert_eq!(graph.graph.edge_values(0, 1), None);
+        assert_eq!(graph.values[&0].values["dir"], Value::Int(0));
+        assert_eq!(graph.graph.get_ancestor(1), Some(0));
+        assert_eq!(graph.graph.get_ancestor(2), Some(1));
+        assert!(graph.graph.has_edge(0, 2).unwrap());
+    }
+
+    fn path(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_source_map() {
+        let map = SourceMap::new(
+            r#"rules:
+  - from:
+      nodes: [{id: 0}, {id: 1}]
+    to:
+      - merge: [0, 0, 1]
+      - delete: 1
+"#,
+        )
+        .unwrap();
+        assert_eq!(map.locate(&path(&["rules"])), Some((1, 0)));
+        assert_eq!(map.locate(&path(&["rules", "0"])), Some((2, 4)));
+        assert_eq!(
+            map.locate(&path(&["rules", "0", "from", "nodes", "1"])),
+            Some((3, 24))
+        );
+        assert_eq!(
+            map.locate(&path(&["rules", "0", "to", "1", "delete"])),
+            Some((6, 8))
+        );
+        // Short forms are located as a whole
+        assert_eq!(
+            map.locate(&path(&["rules", "0", "to", "0", "merge", "targets"])),
+            Some((5, 8))
+        );
+    }
+
+    #[test]
+    fn test_build_axiom_unknown_id() {
+        let axiom: AxiomGraph = serde_yaml::from_str(
+            r#"
+nodes:
+  - {id: 0, name: "stem"}
+edges:
+  - [0, 1]"#,
+        )
+        .unwrap();
+        assert!(axiom.build().is_err());
+    }
+}
@@ -0,0 +1,324 @@
+// Run a plant simulation without a window, for scripts and CI.
+// Nothing in here may depend on Bevy.
+use crate::definition::PlantDefinition;
+use crate::history::History;
+use crate::lint::lint;
+use crate::logger::start_headless_logger;
+use crate::plant::Plant;
+use crate::rgg::{Phase, RggGraph, RuleStats};
+use anyhow::{anyhow, bail, Context};
+use simplelog::LevelFilter;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: plant5-sim <definition> [options]
+       plant5-sim lint <definition>
+
+Commands:
+    lint                    Check the definition's rules for mistakes without growing it
+
+Options:
+    -n, --steps <N>         Number of growth steps to run (default: 1)
+    -f, --format <FORMAT>   Output format: dot, yaml, json or fingerprint (default: dot)
+    -o, --output <FILE>     Write the result to FILE instead of stdout
+    -s, --seed <SEED>       Seed for random decisions (default: from the definition, else random)
+    -p, --param <NAME=VAL>  Override a parameter of the definition. May be given multiple times
+        --stats             Print how much work every rule did to stderr
+        --record <FILE>     Write every step to FILE, as JSON if it ends in .json, else as YAML
+        --replay <FILE>     Grow the plant exactly as recorded in FILE, instead of using -n, -s and -p
+    -v, --verbose           Log rule application to stderr";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Dot,
+    Yaml,
+    Json,
+    /// Only a hash of the graph, to check that runs are reproducible.
+    Fingerprint,
+}
+
+impl OutputFormat {
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "dot" => Ok(Self::Dot),
+            "yaml" => Ok(Self::Yaml),
+            "json" => Ok(Self::Json),
+            "fingerprint" => Ok(Self::Fingerprint),
+            other => bail!("Unknown output format {:?}", other),
+        }
+    }
+
+    /// Render the graph in this format.
+    pub fn write(self, graph: &RggGraph) -> anyhow::Result<String> {
+        Ok(match self {
+            Self::Dot => graph.as_dot_string(),
+            Self::Yaml => serde_yaml::to_string(graph)?,
+            Self::Json => serde_json::to_string_pretty(graph)?,
+            Self::Fingerprint => format!("{:016x}", graph.fingerprint()),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub definition: PathBuf,
+    pub steps: u64,
+    pub format: OutputFormat,
+    pub output: Option<PathBuf>,
+    pub seed: Option<u64>,
+    /// Parameter overrides, applied in order.
+    pub parameters: Vec<(String, f64)>,
+    pub verbose: bool,
+    /// Print the statistics of every rule after growing.
+    pub stats: bool,
+    /// Where to write the history of the plant after growing.
+    pub record: Option<PathBuf>,
+    /// A history to grow the plant from, instead of the steps, seed and parameters.
+    pub replay: Option<PathBuf>,
+}
+
+impl Options {
+    /// Parse the command line arguments, excluding the program name.
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> anyhow::Result<Self> {
+        let mut definition = None;
+        let mut steps = 1;
+        let mut format = OutputFormat::Dot;
+        let mut output = None;
+        let mut seed = None;
+        let mut parameters = vec![];
+        let mut verbose = false;
+        let mut stats = false;
+        let mut record = None;
+        let mut replay = None;
+        let mut steps_given = false;
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| anyhow!("Missing value for {}", name))
+            };
+            match arg.as_str() {
+                "-n" | "--steps" => {
+                    let n = value(&arg)?;
+                    steps = n
+                        .parse()
+                        .with_context(|| format!("Invalid number of steps {:?}", n))?;
+                    steps_given = true;
+                }
+                "-f" | "--format" => format = OutputFormat::parse(&value(&arg)?)?,
+                "-o" | "--output" => output = Some(PathBuf::from(value(&arg)?)),
+                "-s" | "--seed" => {
+                    let s = value(&arg)?;
+                    seed = Some(s.parse().with_context(|| format!("Invalid seed {:?}", s))?);
+                }
+                "-p" | "--param" => {
+                    let p = value(&arg)?;
+                    let mut parts = p.splitn(2, '=');
+                    let (name, number) = match (parts.next(), parts.next()) {
+                        (Some(name), Some(number)) => (name, number),
+                        _ => bail!("Expected NAME=VALUE for a parameter, got {:?}", p),
+                    };
+                    let number = number
+                        .parse()
+                        .with_context(|| format!("Invalid value for parameter {:?}", name))?;
+                    parameters.push((name.to_string(), number));
+                }
+                "-v" | "--verbose" => verbose = true,
+                "--stats" => stats = true,
+                "--record" => record = Some(PathBuf::from(value(&arg)?)),
+                "--replay" => replay = Some(PathBuf::from(value(&arg)?)),
+                flag if flag.starts_with('-') => bail!("Unknown option {:?}", flag),
+                path => {
+                    if definition.replace(PathBuf::from(path)).is_some() {
+                        bail!("Only one plant definition may be given");
+                    }
+                }
+            }
+        }
+
+        if replay.is_some() && (steps_given || seed.is_some() || !parameters.is_empty() || stats) {
+            bail!("--replay can't be combined with --steps, --seed, --param or --stats");
+        }
+
+        Ok(Self {
+            definition: definition.ok_or_else(|| anyhow!("Missing plant definition"))?,
+            steps,
+            format,
+            output,
+            seed,
+            parameters,
+            verbose,
+            stats,
+            record,
+            replay,
+        })
+    }
+}
+
+/// Entry point of the plant5-sim binary. Returns the process exit code.
+pub fn main<I: Iterator<Item = String>>(args: I) -> i32 {
+    let mut args = args.peekable();
+    if args.peek().map(String::as_str) == Some("lint") {
+        args.next();
+        return lint_main(args);
+    }
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    start_headless_logger(if options.verbose {
+        LevelFilter::Info
+    } else {
+        LevelFilter::Warn
+    });
+
+    match run(&options) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("{:?}", e);
+            1
+        }
+    }
+}
+
+/// Print the mistakes in a plant definition. Returns the process exit code,
+/// which is 1 if there are any.
+fn lint_main<I: Iterator<Item = String>>(mut args: I) -> i32 {
+    let path = match (args.next(), args.next()) {
+        (Some(path), None) if !path.starts_with('-') => PathBuf::from(path),
+        _ => {
+            eprintln!("Expected a single plant definition to lint\n\n{}", USAGE);
+            return 2;
+        }
+    };
+    match lint(&path) {
+        Ok(problems) if problems.is_empty() => 0,
+        Ok(problems) => {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            1
+        }
+        Err(e) => {
+            eprintln!("{:?}", e);
+            1
+        }
+    }
+}
+
+/// Grow the plant for the requested number of steps, or as recorded, and write out the final graph.
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    let definition = PlantDefinition::load(&options.definition)?;
+    let mut plant = match options.seed {
+        Some(seed) => Plant::with_seed(0, &definition, seed)?,
+        None => Plant::from_definition(0, &definition)?,
+    };
+    if options.record.is_some() {
+        plant.record_history()?;
+    }
+    if let Some(path) = &options.replay {
+        let history = History::load(path)?;
+        plant.replay(&history)?;
+        log::info!(
+            "Replayed {} steps from {}",
+            history.steps.len(),
+            path.display()
+        );
+        return write_output(options, &plant);
+    }
+    for (name, value) in &options.parameters {
+        if !definition.parameters.contains_key(name) {
+            log::warn!("Parameter {:?} is not defined by the definition", name);
+        }
+        plant.set_parameter(name, *value);
+    }
+    let mut stats: Vec<RuleStats> = vec![];
+    for step in 0..options.steps {
+        let result = match plant.do_rules() {
+            Ok(result) => result,
+            Err(e) => {
+                // The failed step is recorded too, so it can be looked into
+                if let (Some(path), Some(history)) = (&options.record, plant.history()) {
+                    history.save(path)?;
+                }
+                return Err(e.into());
+            }
+        };
+        stats.resize(result.stats.len(), RuleStats::default());
+        for (total, step_stats) in stats.iter_mut().zip(&result.stats) {
+            total.add(step_stats);
+        }
+        log::info!(
+            "Step {}: {} added, {} removed, {} modified",
+            step + 1,
+            result.added.len(),
+            result.removed.len(),
+            result.modified.len()
+        );
+        for failure in &result.failed {
+            log::warn!(
+                "Step {}: procedure {} failed for match {:?} and was rolled back: {}",
+                step + 1,
+                failure.procedure,
+                failure.mapping,
+                failure.error
+            );
+        }
+    }
+
+    if options.stats {
+        eprint!("{}", stats_table(plant.phases(), &stats));
+    }
+    write_output(options, &plant)
+}
+
+/// Write the history if requested, and the final graph.
+fn write_output(options: &Options, plant: &Plant) -> anyhow::Result<()> {
+    if let (Some(path), Some(history)) = (&options.record, plant.history()) {
+        history.save(path)?;
+    }
+
+    let mut rendered = options.format.write(&plant.graph)?;
+    if !rendered.ends_with('\n') {
+        rendered.push('\n');
+    }
+    match &options.output {
+        Some(path) => std::fs::write(path, rendered)
+            .with_context(|| format!("Could not write to {}", path.display()))?,
+        None => std::io::stdout().write_all(rendered.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+/// Format the statistics of every rule as a table. Rules are numbered in the order they appear
+/// in the phases.
+fn stats_table(phases: &[Phase], stats: &[RuleStats]) -> String {
+    let phase_names = phases.iter().flat_map(|phase| {
+        phase
+            .groups
+            .iter()
+            .flat_map(move |group| group.rules.iter().map(move |_| phase.name.as_str()))
+    });
+    let mut table = format!(
+        "{:>4}  {:<12} {:>8} {:>8} {:>8} {:>8} {:>12} {:>12}\n",
+        "rule", "phase", "matches", "skipped", "applied", "failed", "matching ms", "applying ms"
+    );
+    for (index, (phase, stats)) in phase_names.zip(stats).enumerate() {
+        table.push_str(&format!(
+            "{:>4}  {:<12} {:>8} {:>8} {:>8} {:>8} {:>12.3} {:>12.3}\n",
+            index,
+            phase,
+            stats.matches,
+            stats.skipped,
+            stats.applied,
+            stats.failed,
+            stats.matching.as_secs_f64() * 1000.0,
+            stats.applying.as_secs_f64() * 1000.0
+        ));
+    }
+    table
+}
@@ -0,0 +1,166 @@
+// A log of how a plant grew, to find out which rewrite produced a part of it and to replay it.
+// Nothing in here may depend on Bevy.
+use crate::rgg::expr::Variables;
+use crate::rgg::AppliedMatch;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Everything that happened while growing a plant. Together with the plant's definition, it is
+/// enough to grow exactly the same plant again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    /// The seed the plant was grown from.
+    pub seed: u64,
+    /// The fingerprint of the plant definition, if it is known.
+    #[serde(default)]
+    pub definition: Option<u64>,
+    /// One record per step, in order.
+    pub steps: Vec<StepRecord>,
+}
+
+/// What a single step of growth did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepRecord {
+    /// The generation the plant is at after the step, starting at 1. Failed steps don't change it.
+    pub generation: u64,
+    /// The parameters the rules were applied with.
+    pub parameters: Variables,
+    /// Every match that was applied, in order. Rules are numbered in the order they appear in the phases.
+    /// Empty if the step failed, since a failed step leaves the plant as it was.
+    pub matches: Vec<AppliedMatch>,
+    /// Why the step failed, if it did.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// The fingerprint of the graph after the step, to notice when a replay goes differently.
+    pub fingerprint: u64,
+}
+
+impl History {
+    pub fn new(seed: u64, definition: Option<u64>) -> Self {
+        Self {
+            seed,
+            definition,
+            steps: vec![],
+        }
+    }
+
+    /// The generation the plant has after all recorded steps.
+    pub fn generation(&self) -> u64 {
+        self.steps.last().map_or(0, |step| step.generation)
+    }
+
+    /// The step and match that added a node, if it wasn't part of the axiom.
+    pub fn created_by(&self, node: usize) -> Option<(&StepRecord, &AppliedMatch)> {
+        self.steps.iter().find_map(|step| {
+            step.matches
+                .iter()
+                .find(|applied| applied.added.contains(&node))
+                .map(|applied| (step, applied))
+        })
+    }
+
+    /// Every step and match that removed, modified or added a node, in order.
+    pub fn changes_to(&self, node: usize) -> Vec<(&StepRecord, &AppliedMatch)> {
+        self.steps
+            .iter()
+            .flat_map(|step| step.matches.iter().map(move |applied| (step, applied)))
+            .filter(|(_, applied)| {
+                applied.added.contains(&node)
+                    || applied.modified.contains(&node)
+                    || applied.removed.contains(&node)
+            })
+            .collect()
+    }
+
+    /// Read a history written by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read history {}", path.display()))?;
+        Ok(if is_json(path) {
+            serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid history {}", path.display()))?
+        } else {
+            serde_yaml::from_str(&contents)
+                .with_context(|| format!("Invalid history {}", path.display()))?
+        })
+    }
+
+    /// Write the history as JSON if the file name ends in `.json`, otherwise as YAML.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let contents = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            serde_yaml::to_string(self)?
+        };
+        std::fs::write(path, contents)
+            .with_context(|| format!("Could not write history to {}", path.display()))
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension() == Some("json".as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(rule: usize, added: Vec<usize>, modified: Vec<usize>) -> AppliedMatch {
+        AppliedMatch {
+            rule,
+            mapping: maplit::btreemap! {0 => 0},
+            removed: vec![],
+            added,
+            modified,
+            modified_edges: vec![],
+            added_edges: vec![],
+            removed_edges: vec![],
+        }
+    }
+
+    fn history() -> History {
+        let step = |generation, matches| StepRecord {
+            generation,
+            parameters: maplit::btreemap! {"growth".to_string() => 1.5},
+            matches,
+            error: None,
+            fingerprint: generation,
+        };
+        History {
+            seed: 7,
+            definition: Some(0x1234),
+            steps: vec![
+                step(1, vec![applied(0, vec![2], vec![])]),
+                step(
+                    2,
+                    vec![applied(0, vec![3], vec![]), applied(1, vec![], vec![2])],
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_created_by() {
+        let history = history();
+        let (step, applied) = history.created_by(3).unwrap();
+        assert_eq!(step.generation, 2);
+        assert_eq!(applied.rule, 0);
+        assert!(history.created_by(0).is_none());
+
+        let changes = history.changes_to(2);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].1.rule, 1);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let history = history();
+        let yaml = serde_yaml::to_string(&history).unwrap();
+        assert_eq!(serde_yaml::from_str::<History>(&yaml).unwrap(), history);
+        let json = serde_json::to_string(&history).unwrap();
+        assert_eq!(serde_json::from_str::<History>(&json).unwrap(), history);
+    }
+}
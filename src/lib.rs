@@ -0,0 +1,12 @@
+// Grow plants with Relational Growth Grammars.
+// Everything except the `render` module is independent of Bevy, so the engine can be used headless
+// by building with `default-features = false`.
+pub mod definition;
+pub mod headless;
+pub mod history;
+pub mod lint;
+pub mod logger;
+pub mod plant;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod rgg;
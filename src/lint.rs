@@ -0,0 +1,67 @@
+// Checking plant definitions for mistakes without growing them.
+// Nothing in here may depend on Bevy.
+use crate::definition::PlantDefinition;
+use anyhow::Context;
+use std::path::Path;
+
+/// Find the mistakes in a plant definition file without growing it.
+pub fn lint<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<String>> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read plant definition {}", path.display()))?;
+    Ok(PlantDefinition::check(&contents, path)?.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let (_, problems) = PlantDefinition::check(
+            r#"
+parameters: {min_len: 1}
+rules:
+  - from:
+      nodes: [{id: 0, name: stem}]
+    to:
+      - delete: 0
+  - from:
+      nodes: [{id: 0, name: stem}]
+    to:
+      - add: {neighbors: [1], node: {name: leaf, values: {}}}
+  - from:
+      nodes:
+        - {id: 0, name: stem, values: {len: [gte, {param: min_len}]}}
+        - {id: 1, name: stem}
+      edges:
+        - from: 0
+          to: 1
+          values: {angle: [lt, {param: max_angle}]}
+    to: []
+axiom:
+  nodes:
+    - {id: 0, name: stem}
+"#,
+            Path::new("plant.yaml"),
+        )
+        .unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                "plant.yaml:11:8: rules.1.to.0.add: Node 1 is not in the pattern",
+                "plant.yaml:19:19: rules.2.from.edges.0.values.angle: Condition refers to undefined parameter max_angle",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bundled_plants() {
+        let (_, problems) = PlantDefinition::check(
+            include_str!("../plants/basic.yaml"),
+            Path::new("plants/basic.yaml"),
+        )
+        .unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+}
@@ -1,10 +1,19 @@
 use simplelog::{ConfigBuilder, LevelFilter, TermLogger, TerminalMode};
 
 pub fn start_logger() {
+    start_logger_with(LevelFilter::Debug, TerminalMode::Mixed);
+}
+
+/// Start a logger that never writes to stdout, so it can be used for program output.
+pub fn start_headless_logger(level: LevelFilter) {
+    start_logger_with(level, TerminalMode::Stderr);
+}
+
+fn start_logger_with(level: LevelFilter, mode: TerminalMode) {
     let config = ConfigBuilder::new()
         .set_location_level(LevelFilter::Error)
         .add_filter_ignore_str("gfx_backend_vulkan")
         .add_filter_ignore_str("naga")
         .build();
-    TermLogger::init(LevelFilter::Debug, config, TerminalMode::Mixed).unwrap();
+    TermLogger::init(level, config, mode).unwrap();
 }
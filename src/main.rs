@@ -1,109 +1,17 @@
-mod logger;
-mod panorbit;
-mod plant;
-mod rgg;
-mod shapes;
-
-use crate::logger::start_logger;
-use crate::panorbit::{pan_orbit_camera, spawn_camera};
-use crate::plant::{spawn_node, spawn_plant_nodes};
-use crate::rgg::rule::RuleResult;
-use crate::rgg::{RggGraph, Rule};
-use crate::shapes::get_mesh;
 use bevy::ecs::bevy_utils::HashMap;
 use bevy::prelude::*;
 use bevy::utils::AHashExt;
-use gamma::graph::AppendableGraph;
+use plant5::definition::PlantDefinition;
+use plant5::logger::start_logger;
+use plant5::plant::Plant;
+use plant5::render::panorbit::{pan_orbit_camera, spawn_camera};
+use plant5::render::shapes::get_mesh;
+use plant5::render::{node_rotation, spawn_node, spawn_plant_nodes, PlantNode};
 
 struct Tick(u64);
 
-fn get_test_rules() -> Vec<Rule> {
-    serde_yaml::from_str(
-        r#"
-# Split a 2stem into a 3stem
-- from:
-    nodes:
-        - {id: 0, name: "stem"}
-        - {id: 1, name: "stem"}
-    edges:
-        - [0, 1]
-  to:
-    - add:
-        neighbors: [1]
-        node:
-          name: "stem"
-          values:
-            dir: dir + 1
-# Create a sideshoot if it doesn't already have one
-- from:
-    nodes:
-      - id: 0
-        name: "stem"
-        values:
-          sprouted: [eq, 0]
-  to:
-    - replace:
-        target: 0
-        with:
-          name: "stem"
-          values:
-            sprouted: 1
-            dir: dir
-    - add:
-        neighbors: [0]
-        node:
-          name: "shoot"
-          values:
-            rotation: 90 * dir
-    "#,
-    )
-    .unwrap()
-}
-
-fn get_test_plant(id: usize) -> Plant {
-    let mut plant = Plant {
-        id,
-        rules: get_test_rules(),
-        graph: RggGraph::default(),
-    };
-    plant.graph.insert_node_with(crate::rgg::Node {
-        name: "stem".to_string(),
-        values: serde_yaml::from_str("{dir: 0, sprouted: 0}").unwrap(),
-    });
-    plant.graph.insert_node_with(crate::rgg::Node {
-        name: "stem".to_string(),
-        values: serde_yaml::from_str("{dir: 0, sprouted: 0}").unwrap(),
-    });
-    plant.graph.graph.add_edge(0, 1).unwrap();
-    plant.graph.graph.add_ancestor(1, 0);
-    plant
-}
-
-/// The container for all the actual entities that form a plant.
-pub struct Plant {
-    pub id: usize,
-    pub rules: Vec<Rule>,
-    pub graph: RggGraph,
-}
-
-impl Plant {
-    pub fn do_rules(&mut self) -> RuleResult {
-        let mut result = RuleResult::new();
-        for rule in &self.rules {
-            result.add(rule.apply(&mut self.graph));
-        }
-
-        result
-    }
-}
-
-/// Represents the corresponding visual part of a plant.
-struct PlantNode {
-    pub plant_id: usize,
-    pub node_id: usize,
-    /// The coordinate to attach any children of this plant node
-    pub node_offset: Vec3,
-}
+/// The plant definition to load if none is given on the command line.
+const DEFAULT_PLANT: &str = "plants/basic.yaml";
 
 fn update_plants(
     commands: &mut Commands,
@@ -112,6 +20,7 @@ fn update_plants(
     tick: Res<Tick>,
     mut plant_query: Query<(&mut Plant,)>,
     node_query: Query<(&PlantNode, Entity, &Handle<Mesh>)>,
+    parent_query: Query<(&PlantNode, &Parent)>,
     mut offset_query: Query<(&PlantNode, &mut Transform)>,
 ) {
     let mut entities = HashMap::new();
@@ -119,15 +28,26 @@ fn update_plants(
     let mut mesh_handles = HashMap::new();
     // store offsets that need editing
     let mut edit_offsets = HashMap::new();
+    let mut edit_rotations = HashMap::new();
     for (node, entity, mesh) in node_query.iter() {
         entities.insert((node.plant_id, node.node_id), entity);
         offsets.insert((node.plant_id, node.node_id), node.node_offset);
         mesh_handles.insert((node.plant_id, node.node_id), (*mesh).clone());
     }
+    let mut parents = HashMap::new();
+    for (node, parent) in parent_query.iter() {
+        parents.insert((node.plant_id, node.node_id), parent.0);
+    }
 
     for (mut plant,) in plant_query.iter_mut() {
         if tick.0 > 3 && plant.graph.order() < 5 {
-            let results = plant.do_rules();
+            let results = match plant.do_rules() {
+                Ok(results) => results,
+                Err(e) => {
+                    log::error!("Could not grow plant {}: {}", plant.id, e);
+                    continue;
+                }
+            };
             log::debug!("Rule results for plant {}: {:?}", plant.id, results);
             // Handle added
             for id in results.added {
@@ -155,6 +75,69 @@ fn update_plants(
                     edit_offsets.insert((plant.id, child), offset);
                 }
             }
+            // Handle removed edges, which may detach a node from its parent or move it to another
+            let mut reparented = Vec::new();
+            for (a, b) in results.removed_edges {
+                for (child, old_parent) in &[(a, b), (b, a)] {
+                    let child_entity = match entities.get(&(plant.id, *child)) {
+                        Some(entity) => *entity,
+                        None => continue,
+                    };
+                    let old_entity = entities.get(&(plant.id, *old_parent)).copied();
+                    if parents.get(&(plant.id, *child)).copied() != old_entity
+                        || plant.graph.graph.get_ancestor(*child) == Some(*old_parent)
+                    {
+                        continue;
+                    }
+                    let new_parent = plant.graph.graph.get_ancestor(*child);
+                    let new_entity =
+                        new_parent.and_then(|parent| entities.get(&(plant.id, parent)).copied());
+                    let offset = match (new_parent, new_entity) {
+                        (Some(parent), Some(parent_entity)) => {
+                            commands.push_children(parent_entity, &[child_entity]);
+                            offsets
+                                .get(&(plant.id, parent))
+                                .copied()
+                                .unwrap_or_default()
+                        }
+                        _ => {
+                            commands.remove_one::<Parent>(child_entity);
+                            Vec3::zero()
+                        }
+                    };
+                    edit_offsets.insert((plant.id, *child), offset);
+                    edit_rotations.insert((plant.id, *child), node_rotation(&plant, *child));
+                    reparented.push(*child);
+                }
+            }
+            // Handle added edges, which may give an existing node a new parent
+            for (a, b) in results.added_edges {
+                for (child, parent) in &[(a, b), (b, a)] {
+                    if plant.graph.graph.get_ancestor(*child) != Some(*parent)
+                        || reparented.contains(child)
+                    {
+                        continue;
+                    }
+                    let child_entity = entities.get(&(plant.id, *child)).copied();
+                    let parent_entity = entities.get(&(plant.id, *parent)).copied();
+                    if let (Some(child_entity), Some(parent_entity)) = (child_entity, parent_entity)
+                    {
+                        commands.push_children(parent_entity, &[child_entity]);
+                        if let Some(offset) = offsets.get(&(plant.id, *parent)) {
+                            edit_offsets.insert((plant.id, *child), *offset);
+                        }
+                        edit_rotations.insert((plant.id, *child), node_rotation(&plant, *child));
+                    }
+                }
+            }
+            // Handle modified edges, which may turn the child end
+            for (a, b) in results.modified_edges {
+                for (child, parent) in &[(a, b), (b, a)] {
+                    if plant.graph.graph.get_ancestor(*child) == Some(*parent) {
+                        edit_rotations.insert((plant.id, *child), node_rotation(&plant, *child));
+                    }
+                }
+            }
 
             // End result
             log::info!(
@@ -170,6 +153,9 @@ fn update_plants(
         if let Some(offset) = edit_offsets.get(&(node.plant_id, node.node_id)) {
             transform.translation = *offset;
         }
+        if let Some(rotation) = edit_rotations.get(&(node.plant_id, node.node_id)) {
+            transform.rotation = *rotation;
+        }
     }
 }
 
@@ -177,8 +163,15 @@ fn setup(
     commands: &mut Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    definition: Res<PlantDefinition>,
 ) {
-    let plant = get_test_plant(0);
+    let plant = match Plant::from_definition(0, &definition) {
+        Ok(plant) => plant,
+        Err(e) => {
+            log::error!("Could not create plant: {:?}", e);
+            return;
+        }
+    };
     spawn_plant_nodes(0, &plant, &mut meshes, &mut materials, commands);
     commands.spawn((plant,)).spawn(LightBundle {
         transform: Transform::from_translation(Vec3::new(4.0, 8.0, 4.0)),
@@ -193,6 +186,17 @@ fn do_tick(mut tick: ResMut<Tick>) {
 fn main() {
     start_logger();
 
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_PLANT.to_string());
+    let definition = match PlantDefinition::load(&path) {
+        Ok(definition) => definition,
+        Err(e) => {
+            log::error!("{:?}", e);
+            std::process::exit(1);
+        }
+    };
+
     App::build()
         .add_plugins(DefaultPlugins)
         .add_startup_system(spawn_camera.system())
@@ -201,5 +205,6 @@ fn main() {
         .add_system(pan_orbit_camera.system())
         .add_system(update_plants.system())
         .add_resource(Tick(0))
+        .add_resource(definition)
         .run();
 }
@@ -1,127 +1,595 @@
-use crate::shapes::{get_color, get_mesh};
-use crate::{Plant, PlantNode};
-use bevy::ecs::Entity;
-use bevy::math::Vec3;
-use bevy::prelude::*;
-use bevy::utils::{AHashExt, HashMap};
-use gamma::graph::Graph;
-
-/// Spawn a new node that corresponds to the provided node id.
-pub fn spawn_node(
-    node_id: usize,
-    plant: &Plant,
-    entities: &mut HashMap<(usize, usize), Entity>,
-    offsets: &mut HashMap<(usize, usize), Vec3>,
-    mesh_handles: &mut HashMap<(usize, usize), Handle<Mesh>>,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    commands: &mut Commands,
-) {
-    // The key used to index into entities/offsets/meshes etc
-    let ident = (plant.id, node_id);
-
-    let mesh = get_mesh(&plant.graph.values[&node_id]);
-    let mesh = meshes.add(mesh);
-    mesh_handles.insert(ident, mesh.clone());
-    let material = materials.add(StandardMaterial {
-        albedo: get_color(plant.graph.values.get(&node_id).unwrap()),
-        ..Default::default()
-    });
-    let parent_node = plant.graph.graph.get_ancestor(node_id);
-    let (offset, parent) = if let Some(parent_node) = parent_node {
-        let offset = offsets
-            .get(&(plant.id, parent_node))
-            .copied()
-            .unwrap_or_else(|| {
-                log::error!("Could not find PlantNode with id {}", parent_node);
-                Vec3::default()
-            });
-        let parent = entities.get(&(plant.id, parent_node)).copied();
-        (offset, parent)
-    } else {
-        (Vec3::zero(), None)
-    };
-    let rotation = match plant.graph.values.get(&node_id) {
-        Some(node) => {
-            if node.name == "shoot" {
-                let degrees = node
-                    .values
-                    .get("rotation")
-                    .map(|val| val.get::<f32>())
-                    .unwrap_or_else(|| 0.0);
-                Quat::from_rotation_x((45f32).to_radians())
-                    * Quat::from_rotation_z(degrees.to_radians())
-            } else {
-                Quat::identity()
-            }
-        }
-        None => Quat::identity(),
-    };
-    let plantnode = PlantNode {
-        plant_id: plant.id,
-        node_id,
-        node_offset: Vec3::new(0.0, 0.0, 1.0),
-    };
-    // Need to insert the plantnode into our tracking dict
-    offsets.insert(ident, plantnode.node_offset);
-
-    let child = commands
-        .spawn((plantnode,))
-        .with_bundle(PbrBundle {
-            mesh,
-            material,
-            transform: Transform {
-                translation: offset,
-                rotation,
-                ..Default::default()
+use crate::definition::{AxiomGraph, PlantDefinition};
+use crate::history::{History, StepRecord};
+use crate::rgg::rule::{RuleResult, RuleStats};
+use crate::rgg::{DerivationMode, Environment, Phase, RggError, RggGraph};
+use anyhow::bail;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// The container for all the actual entities that form a plant.
+pub struct Plant {
+    pub id: usize,
+    /// Run in order in every step. Not public, since every rule needs its own random stream.
+    phases: Vec<Phase>,
+    pub graph: RggGraph,
+    pub derivation: DerivationMode,
+    /// What rules can refer to besides the graph.
+    pub env: Environment,
+    /// The seed the plant was grown from. The same seed and definition always grow the same plant.
+    pub seed: u64,
+    /// One random stream per rule, in the order the rules appear in the phases,
+    /// so changing one rule doesn't change the random decisions of the others.
+    rngs: Vec<ChaCha8Rng>,
+    /// Random stream for resolving conflicts between rules.
+    conflict_rng: ChaCha8Rng,
+    /// What the plant starts out as, to go back to earlier generations.
+    axiom: AxiomGraph,
+    /// The fingerprint of the definition the plant was created from.
+    definition: u64,
+    /// Every step since the axiom, if it is being recorded.
+    history: Option<History>,
+}
+
+/// Everything that growing changes, to put back if going to another generation fails.
+struct Snapshot {
+    graph: RggGraph,
+    env: Environment,
+    seed: u64,
+    rngs: Vec<ChaCha8Rng>,
+    conflict_rng: ChaCha8Rng,
+    history: Option<History>,
+}
+
+impl Plant {
+    /// Create a plant in its initial state as described by a definition.
+    /// Uses the definition's seed, or a random one if it has none.
+    pub fn from_definition(id: usize, definition: &PlantDefinition) -> anyhow::Result<Self> {
+        let seed = definition.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        Self::with_seed(id, definition, seed)
+    }
+
+    /// Create a plant in its initial state, with all random decisions derived from `seed`.
+    pub fn with_seed(id: usize, definition: &PlantDefinition, seed: u64) -> anyhow::Result<Self> {
+        log::info!("Growing plant {} with seed {}", id, seed);
+        let phases = definition.phases()?;
+        let rule_count = phases
+            .iter()
+            .flat_map(|phase| &phase.groups)
+            .map(|group| group.rules.len())
+            .sum::<usize>();
+        Ok(Self {
+            id,
+            phases,
+            graph: definition.axiom.build()?,
+            derivation: definition.derivation,
+            env: Environment {
+                generation: 0,
+                parameters: definition.parameters.clone(),
             },
-            ..Default::default()
+            seed,
+            rngs: (0..rule_count)
+                .map(|index| stream_rng(seed, index as u64))
+                .collect(),
+            conflict_rng: stream_rng(seed, u64::MAX),
+            axiom: definition.axiom.clone(),
+            definition: definition.fingerprint(),
+            history: None,
         })
-        .current_entity()
-        .expect("that we just spawned an entity");
+    }
 
-    entities.insert(ident, child);
-    if let Some(parent) = parent {
-        commands.push_children(parent, &[child]);
-    } else {
-        log::debug!("State: {:?}", plant.graph.graph);
-        log::warn!("Could not find parent node for {:?}", ident)
+    /// Record every step from now on, so they can be undone and replayed.
+    /// Fails if the plant has grown already, since those steps would be missing.
+    pub fn record_history(&mut self) -> anyhow::Result<()> {
+        if self.env.generation > 0 {
+            bail!(
+                "Can't record the history of plant {}, which has grown already",
+                self.id
+            );
+        }
+        self.history = Some(History::new(self.seed, Some(self.definition)));
+        Ok(())
+    }
+
+    /// The steps since the axiom, if they are being recorded.
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Take out everything that growing changes, leaving an empty graph behind.
+    fn take_snapshot(&mut self) -> Snapshot {
+        Snapshot {
+            graph: std::mem::take(&mut self.graph),
+            env: self.env.clone(),
+            seed: self.seed,
+            rngs: self.rngs.clone(),
+            conflict_rng: self.conflict_rng.clone(),
+            history: self.history.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.graph = snapshot.graph;
+        self.env = snapshot.env;
+        self.seed = snapshot.seed;
+        self.rngs = snapshot.rngs;
+        self.conflict_rng = snapshot.conflict_rng;
+        self.history = snapshot.history;
+    }
+
+    /// Go back to the axiom, with the random streams as they were at the start.
+    /// Keeps the current parameters and, if recording, starts a new history.
+    fn restart(&mut self) -> anyhow::Result<()> {
+        self.graph = self.axiom.build()?;
+        self.env.generation = 0;
+        let seed = self.seed;
+        for (index, rng) in self.rngs.iter_mut().enumerate() {
+            *rng = stream_rng(seed, index as u64);
+        }
+        self.conflict_rng = stream_rng(seed, u64::MAX);
+        if let Some(history) = &mut self.history {
+            *history = History::new(seed, Some(self.definition));
+        }
+        Ok(())
+    }
+
+    /// Grow the plant again from its axiom exactly as recorded, including parameter changes.
+    /// The plant takes on the history's seed. Fails if a step grows a different graph than
+    /// recorded, e.g. because the definition changed since, in which case the plant is left as it was.
+    pub fn replay(&mut self, history: &History) -> anyhow::Result<()> {
+        let snapshot = self.take_snapshot();
+        let replayed = self.replay_steps(history);
+        if replayed.is_err() {
+            self.restore(snapshot);
+        }
+        replayed
+    }
+
+    fn replay_steps(&mut self, history: &History) -> anyhow::Result<()> {
+        if let Some(recorded) = history.definition {
+            if recorded != self.definition {
+                bail!(
+                    "The history was recorded with a different plant definition \
+                     (fingerprint {:016x}, this one is {:016x})",
+                    recorded,
+                    self.definition
+                );
+            }
+        }
+        self.seed = history.seed;
+        self.restart()?;
+        for step in &history.steps {
+            self.env.parameters = step.parameters.clone();
+            match (self.do_rules(), &step.error) {
+                (Ok(_), None) | (Err(_), Some(_)) => {}
+                (Ok(_), Some(error)) => bail!(
+                    "Generation {} failed when it was recorded, but not when replaying it: {}",
+                    step.generation,
+                    error
+                ),
+                (Err(error), None) => return Err(error.into()),
+            }
+            if self.graph.fingerprint() != step.fingerprint {
+                bail!(
+                    "Replaying generation {} grew a different graph than recorded. \
+                     Was the history recorded with a different definition?",
+                    step.generation
+                );
+            }
+        }
+        Ok(())
     }
-}
 
-/// Creates all nodes of a plant.
-pub fn spawn_plant_nodes(
-    starting_node: usize,
-    plant: &Plant,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    commands: &mut Commands,
-) {
-    let mut entities = HashMap::new();
-    let mut offsets = HashMap::new();
-    let mut mesh_handles = HashMap::new();
-    spawn_node(
-        starting_node,
-        plant,
-        &mut entities,
-        &mut offsets,
-        &mut mesh_handles,
-        meshes,
-        materials,
-        commands,
-    );
-    for node in plant.graph.graph.nodes().copied() {
-        if node != starting_node {
-            spawn_node(
-                node,
-                plant,
-                &mut entities,
-                &mut offsets,
-                &mut mesh_handles,
-                meshes,
-                materials,
-                commands,
+    /// Go back to an earlier generation by growing the plant again up to it, as recorded.
+    /// Growing from there on uses the current parameters. If that fails, the plant and its
+    /// history are left as they were.
+    pub fn undo_to(&mut self, generation: u64) -> anyhow::Result<()> {
+        let history = match &self.history {
+            Some(history) => history,
+            None => bail!("Plant {} has no history to undo", self.id),
+        };
+        if generation > history.generation() {
+            bail!(
+                "Can't undo to generation {}, plant {} is at generation {}",
+                generation,
+                self.id,
+                history.generation()
             );
         }
+        let kept = History {
+            seed: history.seed,
+            definition: history.definition,
+            steps: history
+                .steps
+                .iter()
+                .take_while(|step| step.generation <= generation)
+                .cloned()
+                .collect(),
+        };
+        // Recording again while replaying leaves exactly the kept steps in the history
+        let parameters = self.env.parameters.clone();
+        self.replay(&kept)?;
+        self.env.parameters = parameters;
+        Ok(())
+    }
+
+    /// The phases that are run in order in every step.
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    /// Set a parameter, overriding the definition's value. Takes effect from the next step on.
+    pub fn set_parameter(&mut self, name: &str, value: f64) {
+        self.env.parameters.insert(name.to_string(), value);
+    }
+
+    /// Do one step of growth. Nodes that are changed during the step are not changed again until the next step.
+    /// Fails if a rule is malformed, in which case the plant is left as it was before the step.
+    /// The statistics of the result are by the position of the rules in the phases.
+    /// When recording, failed steps are recorded as well, so that replaying them fails the same way.
+    pub fn do_rules(&mut self) -> Result<RuleResult, RggError> {
+        let parameters = self.env.parameters.clone();
+        // Everything a step changes besides the generation, to put back if it fails
+        let graph = self.graph.clone();
+        let rngs = self.rngs.clone();
+        let conflict_rng = self.conflict_rng.clone();
+        let result = self.apply_phases();
+        if result.is_err() {
+            self.graph = graph;
+            self.env.generation -= 1;
+            self.rngs = rngs;
+            self.conflict_rng = conflict_rng;
+        }
+        if let Some(history) = &mut self.history {
+            history.steps.push(StepRecord {
+                generation: self.env.generation,
+                parameters,
+                matches: match &result {
+                    Ok(result) => result.applied.clone(),
+                    Err(_) => vec![],
+                },
+                error: result.as_ref().err().map(|error| error.to_string()),
+                fingerprint: self.graph.fingerprint(),
+            });
+        }
+        result
+    }
+
+    fn apply_phases(&mut self) -> Result<RuleResult, RggError> {
+        self.graph.graph.advance_generation();
+        self.env.generation += 1;
+        let mut result = RuleResult::new();
+        let mut first_rule = 0;
+        for phase in &self.phases {
+            log::debug!("Running phase {}", phase.name);
+            for group in &phase.groups {
+                let rules = first_rule..first_rule + group.rules.len();
+                result.add_at(
+                    first_rule,
+                    group.apply(
+                        &mut self.graph,
+                        &mut self.rngs[rules],
+                        self.derivation,
+                        &mut self.conflict_rng,
+                        &self.env,
+                    )?,
+                );
+                first_rule += group.rules.len();
+            }
+        }
+        // Rules that never matched have statistics too
+        result.stats.resize(first_rule, RuleStats::default());
+        Ok(result)
+    }
+}
+
+/// ChaCha produces the same numbers on every platform, unlike StdRng.
+fn stream_rng(seed: u64, stream: u64) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(stream);
+    rng
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgg::Value;
+
+    const STOCHASTIC: &str = r#"
+rules:
+  - from:
+      nodes:
+        - {id: 0, name: "stem"}
+        - {id: 1, name: "stem"}
+      edges:
+        - [0, 1]
+    probability: 0.7
+    to:
+      - add:
+          neighbors: [1]
+          node:
+            name: "stem"
+            values:
+              len: rand(0.5, 1.5)
+              dir: dir + 1
+  - from:
+      nodes:
+        - {id: 0, name: "stem"}
+      not:
+        - nodes:
+            - {id: 1, name: "leaf"}
+          edges:
+            - [0, 1]
+    alternatives:
+      - weight: 2
+        to:
+          - add: {neighbors: [0], node: {name: "leaf", values: {angle: "rand(0, 90)"}}}
+      - weight: 1
+        to: []
+axiom:
+  nodes:
+    - {id: 0, name: "stem", values: {dir: 0}}
+    - {id: 1, name: "stem", values: {dir: 0}, parent: 0}
+"#;
+
+    fn grow(definition: &str, seed: u64, steps: usize) -> u64 {
+        let definition: PlantDefinition = serde_yaml::from_str(definition).unwrap();
+        let mut plant = Plant::with_seed(0, &definition, seed).unwrap();
+        for _ in 0..steps {
+            plant.do_rules().unwrap();
+        }
+        plant.graph.fingerprint()
+    }
+
+    /// If these change, either the simulation is no longer deterministic or its results changed
+    /// on purpose, in which case the stored fingerprints need to be updated.
+    #[test]
+    fn test_stored_fingerprints() {
+        assert_eq!(grow(STOCHASTIC, 42, 4), 0xb227_edb1_cbd9_e92f);
+        assert_eq!(
+            grow(include_str!("../plants/basic.yaml"), 0, 3),
+            0x6eae_303e_8777_3ac5
+        );
+    }
+
+    #[test]
+    fn test_parameters() {
+        let definition: PlantDefinition = serde_yaml::from_str(
+            r#"
+parameters: {min_len: 2, growth: 1.5}
+rules:
+  - from:
+      nodes:
+        - {id: 0, name: "stem", values: {len: [gte, {param: min_len}]}}
+    when: n0.len < 2 * min_len
+    to:
+      - replace:
+          target: 0
+          with: {name: "stem", values: {len: len * growth}}
+axiom:
+  nodes:
+    - {id: 0, name: "stem", values: {len: 2.0}}
+    - {id: 1, name: "stem", values: {len: 1.0}, parent: 0}
+"#,
+        )
+        .unwrap();
+        let mut plant = Plant::with_seed(0, &definition, 0).unwrap();
+        let len = |plant: &Plant, id: usize| plant.graph.values[&id].values["len"].clone();
+        plant.do_rules().unwrap();
+        assert_eq!(len(&plant, 0), Value::Float(3.0));
+        assert_eq!(len(&plant, 1), Value::Float(1.0));
+        // Stops growing at twice the minimum
+        plant.do_rules().unwrap();
+        assert_eq!(len(&plant, 0), Value::Float(4.5));
+        plant.do_rules().unwrap();
+        assert_eq!(len(&plant, 0), Value::Float(4.5));
+
+        plant.set_parameter("min_len", 1.0);
+        plant.set_parameter("growth", 2.0);
+        plant.do_rules().unwrap();
+        assert_eq!(len(&plant, 1), Value::Float(2.0));
+    }
+
+    #[test]
+    fn test_rule_stats() {
+        let definition: PlantDefinition = serde_yaml::from_str(STOCHASTIC).unwrap();
+        let mut plant = Plant::with_seed(0, &definition, 0).unwrap();
+        let result = plant.do_rules().unwrap();
+        assert_eq!(result.stats.len(), 2);
+        // Both directions of the edge between the two stems
+        assert_eq!(result.stats[0].matches, 2);
+        let applied = result
+            .stats
+            .iter()
+            .map(|stats| stats.applied)
+            .sum::<usize>();
+        assert_eq!(applied, result.added.len());
+    }
+
+    #[test]
+    fn test_undo() {
+        let definition: PlantDefinition = serde_yaml::from_str(STOCHASTIC).unwrap();
+        let mut plant = Plant::with_seed(0, &definition, 42).unwrap();
+        plant.record_history().unwrap();
+        let mut fingerprints = vec![];
+        for _ in 0..4 {
+            plant.do_rules().unwrap();
+            fingerprints.push(plant.graph.fingerprint());
+        }
+        assert_eq!(fingerprints[3], 0xb227_edb1_cbd9_e92f);
+        assert!(plant.undo_to(5).is_err());
+
+        plant.undo_to(2).unwrap();
+        assert_eq!(plant.env.generation, 2);
+        assert_eq!(plant.graph.fingerprint(), fingerprints[1]);
+        assert_eq!(plant.history().unwrap().steps.len(), 2);
+        // Growing forward again grows the same plant
+        plant.do_rules().unwrap();
+        plant.do_rules().unwrap();
+        assert_eq!(plant.graph.fingerprint(), fingerprints[3]);
+
+        plant.undo_to(0).unwrap();
+        assert_eq!(plant.graph.order(), 2);
+    }
+
+    #[test]
+    fn test_failed_undo_keeps_history() {
+        let definition: PlantDefinition = serde_yaml::from_str(STOCHASTIC).unwrap();
+        let mut plant = Plant::with_seed(0, &definition, 42).unwrap();
+        plant.record_history().unwrap();
+        for _ in 0..3 {
+            plant.do_rules().unwrap();
+        }
+        let history = plant.history().unwrap().clone();
+        let fingerprint = plant.graph.fingerprint();
+
+        // As if the definition had changed since
+        plant.axiom.nodes.pop();
+        assert!(plant.undo_to(1).is_err());
+        assert_eq!(plant.history(), Some(&history));
+        assert_eq!(plant.graph.fingerprint(), fingerprint);
+        assert_eq!(plant.env.generation, 3);
+    }
+
+    #[test]
+    fn test_replay() {
+        let definition: PlantDefinition = serde_yaml::from_str(STOCHASTIC).unwrap();
+        let mut plant = Plant::with_seed(0, &definition, 3).unwrap();
+        plant.record_history().unwrap();
+        plant.do_rules().unwrap();
+        plant.set_parameter("unused", 1.0);
+        plant.do_rules().unwrap();
+        let history = plant.history().unwrap();
+        assert_eq!(history.steps[1].parameters["unused"], 1.0);
+        let added = history.steps[0].matches[0].added[0];
+        assert_eq!(history.created_by(added).unwrap().0.generation, 1);
+
+        let saved = serde_yaml::to_string(history).unwrap();
+        let loaded: History = serde_yaml::from_str(&saved).unwrap();
+        let mut replayed = Plant::with_seed(1, &definition, 0).unwrap();
+        replayed.replay(&loaded).unwrap();
+        assert_eq!(replayed.seed, 3);
+        assert_eq!(replayed.graph.fingerprint(), plant.graph.fingerprint());
+
+        let mut tampered = loaded;
+        tampered.steps[1].fingerprint ^= 1;
+        assert!(replayed.replay(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_failed_steps_are_recorded() {
+        let definition: PlantDefinition = serde_yaml::from_str(
+            r#"
+parameters: {min_len: 0}
+rules:
+  - from:
+      nodes: [{id: 0, name: stem, values: {len: [gte, {param: min_len}]}}]
+    to:
+      - replace: {target: 0, with: {name: stem, values: {len: len + 1}}}
+axiom:
+  nodes:
+    - {id: 0, name: stem, values: {len: 1}}
+"#,
+        )
+        .unwrap();
+        let mut plant = Plant::with_seed(0, &definition, 0).unwrap();
+        plant.record_history().unwrap();
+        plant.do_rules().unwrap();
+        plant.do_rules().unwrap();
+        // Conditions on parameters that don't exist fail the step
+        plant.env.parameters.remove("min_len");
+        assert!(plant.do_rules().is_err());
+        plant.set_parameter("min_len", 0.0);
+        plant.do_rules().unwrap();
+        let history = plant.history().unwrap().clone();
+        let generations = history
+            .steps
+            .iter()
+            .map(|step| (step.generation, step.error.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            generations,
+            vec![(1, false), (2, false), (2, true), (3, false)]
+        );
+        // The error is recorded against the graph as it was before the step
+        assert!(history.steps[2].matches.is_empty());
+        assert_eq!(history.steps[2].fingerprint, history.steps[1].fingerprint);
+
+        let fingerprint = plant.graph.fingerprint();
+        plant.replay(&history).unwrap();
+        assert_eq!(plant.graph.fingerprint(), fingerprint);
+        // The failed step is kept, as it didn't change the plant
+        plant.undo_to(2).unwrap();
+        assert_eq!(plant.history().unwrap().steps.len(), 3);
+    }
+
+    #[test]
+    fn test_failed_step_changes_nothing() {
+        let definition: PlantDefinition = serde_yaml::from_str(
+            r#"
+parameters: {max_len: 10}
+phases:
+  - name: grow
+    groups:
+      - rules:
+          - from:
+              nodes: [{id: 0, name: stem}]
+            to:
+              - replace: {target: 0, with: {name: stem, values: {len: "len + rand(0, 1)"}}}
+  - name: sprout
+    groups:
+      - rules:
+          - from:
+              nodes: [{id: 0, name: stem, values: {len: [lt, {param: max_len}]}}]
+            to:
+              - add: {neighbors: [0], node: {name: leaf, values: {}}}
+axiom:
+  nodes:
+    - {id: 0, name: stem, values: {len: 1.0}}
+"#,
+        )
+        .unwrap();
+        let mut plant = Plant::with_seed(0, &definition, 5).unwrap();
+        let mut unfailed = Plant::with_seed(0, &definition, 5).unwrap();
+        plant.do_rules().unwrap();
+        unfailed.do_rules().unwrap();
+        let fingerprint = plant.graph.fingerprint();
+
+        // The first phase grows the stem before the second one fails
+        plant.env.parameters.remove("max_len");
+        assert!(plant.do_rules().is_err());
+        assert_eq!(plant.graph.fingerprint(), fingerprint);
+        assert_eq!(plant.env.generation, 1);
+
+        // Growing on is as if the step had never been tried
+        plant.set_parameter("max_len", 10.0);
+        plant.do_rules().unwrap();
+        unfailed.do_rules().unwrap();
+        assert_eq!(plant.graph.fingerprint(), unfailed.graph.fingerprint());
+    }
+
+    #[test]
+    fn test_replay_other_definition() {
+        let parse = |contents: &str| PlantDefinition::parse(contents, "plant.yaml").unwrap();
+        let mut plant = Plant::with_seed(0, &parse(STOCHASTIC), 3).unwrap();
+        plant.record_history().unwrap();
+        plant.do_rules().unwrap();
+        let history = plant.history().unwrap().clone();
+        assert!(history.definition.is_some());
+
+        let changed = parse(&STOCHASTIC.replace("probability: 0.7", "probability: 0.8"));
+        let mut replayed = Plant::with_seed(1, &changed, 0).unwrap();
+        let error = replayed.replay(&history).unwrap_err();
+        assert!(
+            error.to_string().contains("different plant definition"),
+            "{}",
+            error
+        );
+        assert_eq!(replayed.env.generation, 0);
+    }
+
+    #[test]
+    fn test_seeds() {
+        assert_eq!(grow(STOCHASTIC, 1, 4), grow(STOCHASTIC, 1, 4));
+        assert_ne!(grow(STOCHASTIC, 1, 4), grow(STOCHASTIC, 2, 4));
     }
 }
@@ -0,0 +1,16 @@
+// Everything needed to draw plants with Bevy.
+pub mod panorbit;
+pub mod shapes;
+mod spawn;
+
+use bevy::math::Vec3;
+
+pub use spawn::{node_rotation, spawn_node, spawn_plant_nodes};
+
+/// Represents the corresponding visual part of a plant.
+pub struct PlantNode {
+    pub plant_id: usize,
+    pub node_id: usize,
+    /// The coordinate to attach any children of this plant node
+    pub node_offset: Vec3,
+}
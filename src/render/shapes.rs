@@ -1,5 +1,5 @@
 // Include primitives for rendering.
-use crate::rgg::Node;
+use crate::rgg::{Node, Value};
 use bevy::render::color::Color;
 use bevy::render::mesh::{
     shape::{Box, Icosphere},
@@ -17,14 +17,17 @@ pub fn get_color(node: &Node) -> Color {
 pub fn get_mesh(node: &Node) -> Mesh {
     match node.name.as_str() {
         "stem" | "shoot" => {
-            let len = node
-                .values
-                .get("len")
-                .map(|v| v.get::<f32>())
-                .unwrap_or_else(|| {
+            let len = match node.values.get("len").map(Value::as_number) {
+                Some(Ok(len)) => len as f32,
+                Some(Err(e)) => {
+                    log::warn!("Invalid len parameter: {}", e);
+                    1.0
+                }
+                None => {
                     log::warn!("Missing len parameter");
                     1.0
-                });
+                }
+            };
             stalk(0.1, len)
         }
         n => {
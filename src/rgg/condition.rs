@@ -1,27 +1,354 @@
+use crate::rgg::expr::Variables;
 use crate::rgg::Value;
+use regex::Regex;
+use std::cmp::Ordering;
+
+/// What a condition compares against.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    Value(Value),
+    /// A parameter of the plant, looked up whenever the condition is checked.
+    Param(String),
+}
+
+impl Operand {
+    /// Compare a value against the operand. Parameters are compared at f64 precision,
+    /// as in expressions, so that conditions and guards agree.
+    /// Returns None if they cannot be compared or the parameter doesn't exist.
+    pub fn compare(&self, value: &Value, parameters: &Variables) -> Option<Ordering> {
+        match self {
+            Operand::Value(operand) => value.compare(operand),
+            Operand::Param(name) => value.as_number().ok()?.partial_cmp(parameters.get(name)?),
+        }
+    }
+}
+
+impl Operand {
+    /// The parameter the operand refers to, if any.
+    fn parameter(&self) -> Option<&str> {
+        match self {
+            Operand::Value(_) => None,
+            Operand::Param(name) => Some(name),
+        }
+    }
+}
+
+impl From<Value> for Operand {
+    fn from(value: Value) -> Self {
+        Operand::Value(value)
+    }
+}
+
+/// Which ends of a range belong to it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bounds {
+    pub low_inclusive: bool,
+    pub high_inclusive: bool,
+}
+
+impl Bounds {
+    /// Read interval notation: `[]`, `[)`, `(]` or `()`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut chars = s.chars();
+        let low_inclusive = match chars.next()? {
+            '[' => true,
+            '(' => false,
+            _ => return None,
+        };
+        let high_inclusive = match chars.next()? {
+            ']' => true,
+            ')' => false,
+            _ => return None,
+        };
+        if chars.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            low_inclusive,
+            high_inclusive,
+        })
+    }
+}
+
+impl Default for Bounds {
+    /// Both ends included, as ranges without bounds always were.
+    fn default() -> Self {
+        Self {
+            low_inclusive: true,
+            high_inclusive: true,
+        }
+    }
+}
+
+/// Matches strings, such as node names.
+#[derive(Debug, Clone)]
+pub enum StringPattern {
+    Exact(String),
+    /// `*` matches any number of characters, `?` matches exactly one.
+    Glob(String),
+    /// Has to match somewhere in the string, unless anchored with `^` and `$`.
+    Regex(Regex),
+}
+
+impl StringPattern {
+    pub fn is_match(&self, s: &str) -> bool {
+        match self {
+            StringPattern::Exact(exact) => exact == s,
+            StringPattern::Glob(glob) => glob_match(glob, s),
+            StringPattern::Regex(regex) => regex.is_match(s),
+        }
+    }
+}
+
+impl PartialEq for StringPattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (StringPattern::Exact(l), StringPattern::Exact(r)) => l == r,
+            (StringPattern::Glob(l), StringPattern::Glob(r)) => l == r,
+            (StringPattern::Regex(l), StringPattern::Regex(r)) => l.as_str() == r.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl From<&str> for StringPattern {
+    fn from(s: &str) -> Self {
+        StringPattern::Exact(s.to_string())
+    }
+}
+
+fn glob_match(glob: &str, s: &str) -> bool {
+    let glob = glob.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+    let (mut g, mut i) = (0, 0);
+    // Where the last star was, and how much of the string it has swallowed so far
+    let mut star: Option<(usize, usize)> = None;
+    while i < s.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, i));
+                g += 1;
+            }
+            Some(c) if *c == '?' || *c == s[i] => {
+                g += 1;
+                i += 1;
+            }
+            _ => match star {
+                // Let the star swallow one more character and try again
+                Some((star_g, star_i)) => {
+                    star = Some((star_g, star_i + 1));
+                    g = star_g + 1;
+                    i = star_i + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == '*')
+}
 
 /// Define a condition to match FromNodes against
 #[derive(Debug, PartialEq, Clone)]
 pub enum Condition {
-    Equals(Value),
-    LessThan(Value),
-    GreaterThan(Value),
-    LessThanOrEquals(Value),
-    GreaterThanOrEquals(Value),
-    /// Greater than Range.0, less than Range.1. Inclusive lower, exclusive upper.
-    Range(Value, Value),
+    Equals(Operand),
+    NotEquals(Operand),
+    LessThan(Operand),
+    GreaterThan(Operand),
+    LessThanOrEquals(Operand),
+    GreaterThanOrEquals(Operand),
+    /// Between Range.0 and Range.1, with the ends included as given by the bounds.
+    Range(Operand, Operand, Bounds),
+    /// Equal to any of the operands.
+    In(Vec<Operand>),
+    /// A string that matches the pattern.
+    Matches(StringPattern),
+    /// The node has the value, whatever it is.
+    Exists,
+    /// The node doesn't have the value.
+    Missing,
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
 }
 
 impl Condition {
-    /// Check whether the provided value fulfils the condition
-    pub fn check<T: PartialEq + Copy + PartialOrd>(&self, value: T) -> bool {
+    /// Check whether the provided value fulfils the condition, where None means the node doesn't
+    /// have the value. Only `missing` and negations are fulfilled by a missing value.
+    /// Values that cannot be compared with the condition, such as a string against a number,
+    /// never fulfil it, and neither do conditions on parameters that don't exist. Patterns are checked
+    /// for those before matching, see `NodeSet::check_parameters`.
+    pub fn check(&self, value: Option<&Value>, parameters: &Variables) -> bool {
+        match (self, value) {
+            (Self::Exists, value) => value.is_some(),
+            (Self::Missing, value) => value.is_none(),
+            (Self::And(conditions), value) => conditions.iter().all(|c| c.check(value, parameters)),
+            (Self::Or(conditions), value) => conditions.iter().any(|c| c.check(value, parameters)),
+            (Self::Not(condition), value) => !condition.check(value, parameters),
+            (_, None) => false,
+            (_, Some(value)) => self.check_value(value, parameters),
+        }
+    }
+
+    /// The names of all parameters the condition refers to.
+    pub fn parameters(&self) -> Vec<&str> {
+        match self {
+            Self::Equals(operand)
+            | Self::NotEquals(operand)
+            | Self::LessThan(operand)
+            | Self::GreaterThan(operand)
+            | Self::LessThanOrEquals(operand)
+            | Self::GreaterThanOrEquals(operand) => operand.parameter().into_iter().collect(),
+            Self::Range(low, high, _) => low
+                .parameter()
+                .into_iter()
+                .chain(high.parameter())
+                .collect(),
+            Self::In(options) => options.iter().filter_map(Operand::parameter).collect(),
+            Self::And(conditions) | Self::Or(conditions) => conditions
+                .iter()
+                .flat_map(|condition| condition.parameters())
+                .collect(),
+            Self::Not(condition) => condition.parameters(),
+            Self::Matches(_) | Self::Exists | Self::Missing => vec![],
+        }
+    }
+
+    /// Check the conditions that compare against an existing value.
+    fn check_value(&self, value: &Value, parameters: &Variables) -> bool {
+        let cmp = |operand: &Operand| operand.compare(value, parameters);
+        let above = |low: &Operand, inclusive: bool| match cmp(low) {
+            Some(Ordering::Greater) => true,
+            Some(Ordering::Equal) => inclusive,
+            _ => false,
+        };
+        let below = |high: &Operand, inclusive: bool| match cmp(high) {
+            Some(Ordering::Less) => true,
+            Some(Ordering::Equal) => inclusive,
+            _ => false,
+        };
         match self {
-            Self::Equals(condition) => value == condition.get::<T>(),
-            Self::LessThan(condition) => value < condition.get::<T>(),
-            Self::GreaterThan(condition) => value > condition.get::<T>(),
-            Self::LessThanOrEquals(condition) => value <= condition.get::<T>(),
-            Self::GreaterThanOrEquals(condition) => value >= condition.get::<T>(),
-            Self::Range(l, r) => l.get::<T>() <= value && value <= r.get::<T>(),
+            Self::Equals(condition) => cmp(condition) == Some(Ordering::Equal),
+            Self::NotEquals(condition) => {
+                matches!(cmp(condition), Some(o) if o != Ordering::Equal)
+            }
+            Self::LessThan(condition) => cmp(condition) == Some(Ordering::Less),
+            Self::GreaterThan(condition) => cmp(condition) == Some(Ordering::Greater),
+            Self::LessThanOrEquals(condition) => below(condition, true),
+            Self::GreaterThanOrEquals(condition) => above(condition, true),
+            Self::Range(l, r, bounds) => {
+                above(l, bounds.low_inclusive) && below(r, bounds.high_inclusive)
+            }
+            Self::In(options) => options
+                .iter()
+                .any(|option| cmp(option) == Some(Ordering::Equal)),
+            Self::Matches(pattern) => match value {
+                Value::String(s) => pattern.is_match(s),
+                _ => false,
+            },
+            // Don't depend on the value alone, see check()
+            Self::Exists | Self::Missing | Self::And(_) | Self::Or(_) | Self::Not(_) => false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(condition: &Condition, value: Option<Value>) -> bool {
+        condition.check(value.as_ref(), &Variables::new())
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_match("st*", "stem"));
+        assert!(glob_match("*em", "stem"));
+        assert!(glob_match("s?e*", "stem"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("st?", "stem"));
+        assert!(!glob_match("leaf", "stem"));
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let range = |bounds: &str| {
+            Condition::Range(
+                Value::Int(0).into(),
+                Value::Int(2).into(),
+                Bounds::parse(bounds).unwrap(),
+            )
+        };
+        let ends = |bounds: &str| {
+            (
+                check(&range(bounds), Some(Value::Int(0))),
+                check(&range(bounds), Some(Value::Int(2))),
+            )
+        };
+        assert_eq!(ends("[)"), (true, false));
+        assert_eq!(ends("[]"), (true, true));
+        assert_eq!(ends("(]"), (false, true));
+        assert_eq!(ends("()"), (false, false));
+        assert!(check(&range("()"), Some(Value::Float(1.5))));
+        assert_eq!(Bounds::parse("[]"), Some(Bounds::default()));
+        assert_eq!(Bounds::parse("[[]"), None);
+    }
+
+    #[test]
+    fn test_param_precision() {
+        let parameters = maplit::btreemap! {"big".to_string() => 16_777_217.0};
+        let check =
+            |condition: Condition| condition.check(Some(&Value::Int(16_777_217)), &parameters);
+        // Not representable as f32, where it would round to 16777216
+        assert!(check(Condition::Equals(Operand::Param("big".to_string()))));
+        assert!(!check(Condition::GreaterThan(Operand::Param(
+            "big".to_string()
+        ))));
+        assert!(!check(Condition::Equals(Operand::Param(
+            "missing".to_string()
+        ))));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let between = Condition::And(vec![
+            Condition::GreaterThan(Value::Int(1).into()),
+            Condition::LessThan(Value::Int(5).into()),
+        ]);
+        assert!(check(&between, Some(Value::Int(3))));
+        assert!(!check(&between, Some(Value::Int(5))));
+
+        let small_or_missing = Condition::Or(vec![
+            Condition::Missing,
+            Condition::LessThan(Value::Int(1).into()),
+        ]);
+        assert!(check(&small_or_missing, None));
+        assert!(check(&small_or_missing, Some(Value::Int(0))));
+        assert!(!check(&small_or_missing, Some(Value::Int(1))));
+
+        let not_three = Condition::Not(Box::new(Condition::Equals(Value::Int(3).into())));
+        assert!(check(&not_three, None));
+        assert!(!check(&not_three, Some(Value::Int(3))));
+        // Unlike a negated eq, ne needs a comparable value
+        let ne = Condition::NotEquals(Value::Int(3).into());
+        assert!(!check(&ne, None));
+        assert!(!check(&ne, Some(Value::from("three"))));
+        assert!(check(&ne, Some(Value::Float(3.5))));
+    }
+
+    #[test]
+    fn test_in_and_matches() {
+        let options = Condition::In(vec![Value::Int(1).into(), Value::from("two").into()]);
+        assert!(check(&options, Some(Value::Float(1.0))));
+        assert!(check(&options, Some(Value::from("two"))));
+        assert!(!check(&options, Some(Value::Int(2))));
+
+        let pattern =
+            Condition::Matches(StringPattern::Regex(Regex::new("^le(af|aves)$").unwrap()));
+        assert!(check(&pattern, Some(Value::from("leaves"))));
+        assert!(!check(&pattern, Some(Value::from("leafy"))));
+        assert!(!check(&pattern, Some(Value::Int(1))));
+        assert!(check(&Condition::Exists, Some(Value::Int(1))));
+        assert!(!check(&Condition::Exists, None));
+    }
+}
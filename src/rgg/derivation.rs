@@ -0,0 +1,263 @@
+// Ways of applying all the rules of a plant to its graph in a single step.
+use crate::rgg::rule::{RuleMatch, RuleResult, RuleStats};
+use crate::rgg::{Environment, RggError, RggGraph, Rule};
+use rand::seq::SliceRandom;
+use rand::RngCore;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How the rules are applied in each step.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DerivationMode {
+    /// Each rule is matched and applied in turn, and sees the changes made by the rules before it.
+    Sequential,
+    /// All rules are matched against the same graph, then all matches that don't conflict
+    /// are applied at once, as in an L-system.
+    Parallel(ConflictPolicy),
+}
+
+impl Default for DerivationMode {
+    fn default() -> Self {
+        DerivationMode::Sequential
+    }
+}
+
+/// Decides which match is applied when several matches of a parallel step conflict.
+/// Two matches conflict if one of them deletes or changes a node that the other one matched.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Matches of earlier rules win, and earlier matches of the same rule win.
+    FirstWins,
+    /// Matches of rules with a higher priority win. Ties are broken as for FirstWins.
+    Priority,
+    /// The matches are considered in a random order.
+    Random,
+}
+
+/// Apply the rules one after the other. `rngs` holds the random stream of every rule.
+/// If `exclusive` is set, a rule is not applied to matches that include nodes an earlier rule
+/// was already applied to, and those matches count as skipped.
+pub fn sequential_step<R: RngCore>(
+    rules: &[Rule],
+    graph: &mut RggGraph,
+    rngs: &mut [R],
+    exclusive: bool,
+    env: &Environment,
+) -> Result<RuleResult, RggError> {
+    let mut result = RuleResult::new();
+    let mut claimed = BTreeSet::new();
+    for (index, (rule, rng)) in rules.iter().zip(rngs.iter_mut()).enumerate() {
+        if !exclusive {
+            result.add_at(index, rule.apply(graph, rng, env)?);
+            continue;
+        }
+        let (candidates, stats) = rule.select_matches(graph, rng, env)?;
+        result.add_at(index, RuleResult::with_stats(stats));
+        let mut claimed_by_rule = vec![];
+        for candidate in candidates {
+            if candidate
+                .mapping
+                .values()
+                .any(|node| claimed.contains(node))
+            {
+                result.add_at(index, skipped());
+                continue;
+            }
+            claimed_by_rule.extend(candidate.mapping.values().copied());
+            result.add_at(index, candidate.apply(graph, rng, env));
+        }
+        claimed.extend(claimed_by_rule);
+    }
+    Ok(result)
+}
+
+/// Apply the rules in parallel. `rngs` holds the random stream of every rule, and `rng` is used
+/// to resolve conflicts randomly. If `exclusive` is set, matches of different rules
+/// that share any node conflict as well. Matches that lose a conflict count as skipped.
+pub fn parallel_step<R: RngCore>(
+    rules: &[Rule],
+    graph: &mut RggGraph,
+    rngs: &mut [R],
+    policy: ConflictPolicy,
+    exclusive: bool,
+    rng: &mut dyn RngCore,
+    env: &Environment,
+) -> Result<RuleResult, RggError> {
+    // Match every rule against the unchanged graph
+    let mut result = RuleResult::new();
+    let mut candidates: Vec<(usize, RuleMatch)> = vec![];
+    for (index, (rule, rule_rng)) in rules.iter().zip(rngs.iter_mut()).enumerate() {
+        let (selected, stats) = rule.select_matches(graph, rule_rng, env)?;
+        result.add_at(index, RuleResult::with_stats(stats));
+        candidates.extend(selected.into_iter().map(|selected| (index, selected)));
+    }
+
+    match policy {
+        ConflictPolicy::FirstWins => {}
+        // Stable, so earlier rules still come first within the same priority
+        ConflictPolicy::Priority => {
+            candidates.sort_by_key(|(index, _)| Reverse(rules[*index].priority))
+        }
+        ConflictPolicy::Random => candidates.shuffle(rng),
+    }
+
+    // Accept matches greedily. Remember which rule matched each node.
+    let mut matched = BTreeMap::new();
+    let mut rewritten = BTreeSet::new();
+    let mut accepted = vec![];
+    let mut conflicts = 0;
+    for (index, candidate) in candidates {
+        let rewrites = candidate.rewritten_nodes();
+        if rewrites.iter().any(|node| matched.contains_key(node))
+            || candidate.mapping.values().any(|node| {
+                rewritten.contains(node)
+                    || (exclusive && matches!(matched.get(node), Some(rule) if *rule != index))
+            })
+        {
+            conflicts += 1;
+            result.add_at(index, skipped());
+            continue;
+        }
+        matched.extend(candidate.mapping.values().map(|node| (*node, index)));
+        rewritten.extend(rewrites);
+        accepted.push((index, candidate));
+    }
+    if conflicts > 0 {
+        log::debug!("Dropped {} conflicting matches.", conflicts);
+    }
+
+    for (index, candidate) in accepted {
+        result.add_at(index, candidate.apply(graph, &mut rngs[index], env));
+    }
+    Ok(result)
+}
+
+/// The result of a match that was not applied.
+fn skipped() -> RuleResult {
+    RuleResult::with_stats(RuleStats {
+        skipped: 1,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgg::Node;
+    use gamma::graph::AppendableGraph;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn rules(yaml: &str) -> Vec<Rule> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn single_stem() -> RggGraph {
+        let mut graph = RggGraph::new();
+        graph.insert_node_with(Node::new("stem"));
+        graph
+    }
+
+    fn step(rules: &[Rule], graph: &mut RggGraph, policy: ConflictPolicy, seed: u64) -> RuleResult {
+        graph.graph.advance_generation();
+        let mut rngs = vec![StdRng::seed_from_u64(seed); rules.len()];
+        parallel_step(
+            rules,
+            graph,
+            &mut rngs,
+            policy,
+            false,
+            &mut StdRng::seed_from_u64(seed),
+            &Environment::default(),
+        )
+        .unwrap()
+    }
+
+    const COMPETING: &str = r#"
+- from:
+    nodes: [{id: 0, name: stem}]
+  to:
+    - replace: {target: 0, with: {name: leaf, values: {}}}
+- from:
+    nodes: [{id: 0, name: stem}]
+  priority: 1
+  to:
+    - delete: 0
+"#;
+
+    #[test]
+    fn test_parallel_snapshot() {
+        // The second rule still sees the leaf that the first one deletes
+        let rules = rules(
+            r#"
+- from:
+    nodes: [{id: 0, name: leaf}]
+  to:
+    - delete: 0
+- from:
+    nodes: [{id: 0, name: stem}]
+    not:
+      - nodes: [{id: 1, name: leaf}]
+        edges: [[0, 1]]
+  to:
+    - add: {neighbors: [0], node: {name: leaf, values: {}}}
+"#,
+        );
+        let leafy_stem = || {
+            let mut graph = single_stem();
+            let leaf = graph.insert_node_with(Node::new("leaf"));
+            graph.graph.add_edge(0, leaf).unwrap();
+            graph
+        };
+        let mut graph = leafy_stem();
+        step(&rules, &mut graph, ConflictPolicy::FirstWins, 0);
+        assert_eq!(graph.order(), 1);
+
+        // Applied in sequence, the stem gets a new leaf
+        let mut graph = leafy_stem();
+        graph.graph.advance_generation();
+        let mut rng = StdRng::seed_from_u64(0);
+        for rule in &rules {
+            rule.apply(&mut graph, &mut rng, &Environment::default())
+                .unwrap();
+        }
+        assert_eq!(graph.order(), 2);
+    }
+
+    #[test]
+    fn test_first_wins() {
+        let rules = rules(COMPETING);
+        let mut graph = single_stem();
+        let result = step(&rules, &mut graph, ConflictPolicy::FirstWins, 0);
+        assert_eq!(graph.order(), 1);
+        assert_eq!(graph.values[&0].name, "leaf");
+        // The losing match shows up in the statistics
+        let counts = |stats: &RuleStats| (stats.matches, stats.skipped, stats.applied);
+        assert_eq!(counts(&result.stats[0]), (1, 0, 1));
+        assert_eq!(counts(&result.stats[1]), (1, 1, 0));
+    }
+
+    #[test]
+    fn test_priority() {
+        let rules = rules(COMPETING);
+        let mut graph = single_stem();
+        step(&rules, &mut graph, ConflictPolicy::Priority, 0);
+        assert_eq!(graph.order(), 0);
+    }
+
+    #[test]
+    fn test_random() {
+        let rules = rules(COMPETING);
+        let mut outcomes = BTreeSet::new();
+        for seed in 0..20 {
+            let mut graph = single_stem();
+            step(&rules, &mut graph, ConflictPolicy::Random, seed);
+            // Exactly one of the rules was applied
+            outcomes.insert(graph.order());
+        }
+        assert_eq!(outcomes, vec![0, 1].into_iter().collect());
+    }
+}
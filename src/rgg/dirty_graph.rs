@@ -1,3 +1,4 @@
+use crate::rgg::{EdgeLabel, Value};
 use gamma::graph::{AppendableGraph, Error, Graph, RemovableGraph};
 use std::collections::{BTreeMap, BTreeSet};
 
@@ -9,7 +10,42 @@ fn new_edge(node1: usize, node2: usize) -> (usize, usize) {
     }
 }
 
-#[derive(Debug)]
+/// What a node looked like before a transaction first changed it.
+#[derive(Debug, Clone)]
+struct NodeState {
+    exists: bool,
+    adjacency: Option<Vec<usize>>,
+    ancestor: Option<usize>,
+    children: Option<BTreeSet<usize>>,
+    generation: Option<u8>,
+}
+
+/// What an edge looked like before a transaction first changed it.
+#[derive(Debug, Clone)]
+struct EdgeState {
+    exists: bool,
+    label: Option<(EdgeLabel, usize)>,
+    values: Option<BTreeMap<String, Value>>,
+    generation: Option<u8>,
+}
+
+/// Everything that a transaction changed, as it was before the transaction.
+#[derive(Debug, Clone, Default)]
+struct Journal {
+    nodes: BTreeMap<usize, NodeState>,
+    edges: BTreeMap<(usize, usize), EdgeState>,
+    next_node: usize,
+}
+
+/// Set or remove an entry of a map.
+fn restore<K: Ord, V>(map: &mut BTreeMap<K, V>, key: K, value: Option<V>) {
+    match value {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+#[derive(Debug, Clone)]
 /// An implementation of the gamma::Graph API that supports associating an integer to nodes and edges.
 /// Essentially, this allows keeping track of whether a node/edge was already touched this iteration.
 pub struct DirtyGraph {
@@ -18,6 +54,10 @@ pub struct DirtyGraph {
     /// Store the edges that exist. Since DirtyGraph is a non-directed graph, we should always
     /// attempt to store edges sorted so that "from" is always less than or equal to "to".
     edges: BTreeSet<(usize, usize)>,
+    /// The label of every edge, and the node that directed edges start at.
+    edge_labels: BTreeMap<(usize, usize), (EdgeLabel, usize)>,
+    /// Named values of edges, such as angles. Edges without values have no entry.
+    edge_values: BTreeMap<(usize, usize), BTreeMap<String, Value>>,
     /// Store adjancency details: the value is all adjacent elements to the nodeid that is the key.
     /// Each edge is effectively stored as two adjacencies: one from A->B and one from B->A.
     adjacency: BTreeMap<usize, Vec<usize>>,
@@ -35,6 +75,8 @@ pub struct DirtyGraph {
     /// The generation id for the next series of matchings.
     /// If generation id == 255, we need to reset all generations to 0 once we are done with this generation.
     next_generation: u8,
+    /// Records the changes of the current transaction, if there is one.
+    journal: Option<Journal>,
 }
 
 impl Default for DirtyGraph {
@@ -42,6 +84,8 @@ impl Default for DirtyGraph {
         Self {
             nodes: Default::default(),
             edges: Default::default(),
+            edge_labels: Default::default(),
+            edge_values: Default::default(),
             adjacency: Default::default(),
             ancestors: Default::default(),
             children: Default::default(),
@@ -49,23 +93,116 @@ impl Default for DirtyGraph {
             edge_generation: Default::default(),
             next_node: 0,
             next_generation: 1,
+            journal: None,
         }
     }
 }
 
 impl DirtyGraph {
+    /// Remember the state of a node before the current transaction changes it.
+    fn touch_node(&mut self, id: usize) {
+        let journal = match &mut self.journal {
+            Some(journal) if !journal.nodes.contains_key(&id) => journal,
+            _ => return,
+        };
+        let state = NodeState {
+            exists: self.nodes.contains(&id),
+            adjacency: self.adjacency.get(&id).cloned(),
+            ancestor: self.ancestors.get(&id).copied(),
+            children: self.children.get(&id).cloned(),
+            generation: self.node_generation.get(&id).copied(),
+        };
+        journal.nodes.insert(id, state);
+    }
+
+    /// Remember the state of an edge before the current transaction changes it.
+    fn touch_edge(&mut self, edge: (usize, usize)) {
+        let journal = match &mut self.journal {
+            Some(journal) if !journal.edges.contains_key(&edge) => journal,
+            _ => return,
+        };
+        let state = EdgeState {
+            exists: self.edges.contains(&edge),
+            label: self.edge_labels.get(&edge).copied(),
+            values: self.edge_values.get(&edge).cloned(),
+            generation: self.edge_generation.get(&edge).copied(),
+        };
+        journal.edges.insert(edge, state);
+    }
+
+    /// Start recording changes, so that they can be undone with `rollback`.
+    /// Transactions can't be nested: a transaction that is already running just continues.
+    pub fn begin_transaction(&mut self) {
+        if self.journal.is_none() {
+            self.journal = Some(Journal {
+                next_node: self.next_node,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Keep the changes of the current transaction.
+    pub fn commit(&mut self) {
+        self.journal = None;
+    }
+
+    /// Undo all changes since the current transaction began. Advancing the generation is not undone.
+    pub fn rollback(&mut self) {
+        let journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return,
+        };
+        for (id, state) in journal.nodes {
+            if state.exists {
+                self.nodes.insert(id);
+            } else {
+                self.nodes.remove(&id);
+            }
+            restore(&mut self.adjacency, id, state.adjacency);
+            restore(&mut self.ancestors, id, state.ancestor);
+            restore(&mut self.children, id, state.children);
+            restore(&mut self.node_generation, id, state.generation);
+        }
+        for (edge, state) in journal.edges {
+            if state.exists {
+                self.edges.insert(edge);
+            } else {
+                self.edges.remove(&edge);
+            }
+            restore(&mut self.edge_labels, edge, state.label);
+            restore(&mut self.edge_values, edge, state.values);
+            restore(&mut self.edge_generation, edge, state.generation);
+        }
+        self.next_node = journal.next_node;
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.journal.is_some()
+    }
+
     fn add_to_adjacency(&mut self, lhs: usize, rhs: usize) {
+        self.touch_node(lhs);
         (*self.adjacency.entry(lhs).or_insert_with(std::vec::Vec::new)).push(rhs)
     }
 
+    fn remove_from_adjacency(&mut self, lhs: usize, rhs: usize) {
+        self.touch_node(lhs);
+        if let Some(adjacent) = self.adjacency.get_mut(&lhs) {
+            adjacent.retain(|n| *n != rhs);
+        }
+    }
+
     /// Infallible internal method used to implement has_edge()
     fn contains_edge(&self, sid: usize, tid: usize) -> bool {
         let edge = new_edge(sid, tid);
         self.edges.contains(&edge)
     }
 
-    /// Add an ancestor.
+    /// Add an ancestor, replacing any previous one.
     pub fn add_ancestor(&mut self, me: usize, ancestor: usize) {
+        self.remove_ancestor(me);
+        self.touch_node(me);
+        self.touch_node(ancestor);
         self.ancestors.insert(me, ancestor);
         let children = self
             .children
@@ -76,7 +213,15 @@ impl DirtyGraph {
 
     /// Remove an ancestor.
     pub fn remove_ancestor(&mut self, me: usize) {
-        self.ancestors.remove(&me);
+        self.touch_node(me);
+        if let Some(ancestor) = self.ancestors.get(&me).copied() {
+            self.touch_node(ancestor);
+        }
+        if let Some(ancestor) = self.ancestors.remove(&me) {
+            if let Some(children) = self.children.get_mut(&ancestor) {
+                children.remove(&me);
+            }
+        }
     }
 
     /// Remove all the children of 'id' and either assign id's parent as the parents (if possible)
@@ -92,6 +237,7 @@ impl DirtyGraph {
                 None => self.remove_ancestor(child),
             }
         }
+        self.touch_node(id);
         self.children.remove(&id);
     }
 
@@ -118,6 +264,7 @@ impl DirtyGraph {
 
     /// Set the given node as dirty. Returns false if the node didn't exist.
     pub fn set_node_dirty(&mut self, node: usize) -> bool {
+        self.touch_node(node);
         match self.node_generation.get_mut(&node) {
             Some(gen) => {
                 *gen = self.next_generation;
@@ -140,6 +287,84 @@ impl DirtyGraph {
 
         gen >= self.next_generation
     }
+
+    /// Add an edge with a label, or relabel an existing edge. Directed labels point from `from` to `to`.
+    pub fn add_labeled_edge(
+        &mut self,
+        from: usize,
+        to: usize,
+        label: EdgeLabel,
+    ) -> Result<(), Error> {
+        self.add_edge(from, to)?;
+        self.touch_edge(new_edge(from, to));
+        self.edge_labels.insert(new_edge(from, to), (label, from));
+        Ok(())
+    }
+
+    pub fn edge_label(&self, node1: usize, node2: usize) -> Option<EdgeLabel> {
+        self.edge_labels
+            .get(&new_edge(node1, node2))
+            .map(|(label, _)| *label)
+    }
+
+    /// The node a directed edge starts at. None for undirected or missing edges.
+    pub fn edge_source(&self, node1: usize, node2: usize) -> Option<usize> {
+        match self.edge_labels.get(&new_edge(node1, node2)) {
+            Some((label, source)) if label.is_directed() => Some(*source),
+            _ => None,
+        }
+    }
+
+    /// Whether there is an edge with the label, going from `from` to `to` if the label is directed.
+    pub fn has_labeled_edge(&self, from: usize, to: usize, label: EdgeLabel) -> bool {
+        match self.edge_labels.get(&new_edge(from, to)) {
+            Some((l, source)) => *l == label && (!label.is_directed() || *source == from),
+            None => false,
+        }
+    }
+
+    pub fn edge_values(&self, node1: usize, node2: usize) -> Option<&BTreeMap<String, Value>> {
+        self.edge_values.get(&new_edge(node1, node2))
+    }
+
+    /// Set values of an edge, keeping the values that are not given. Returns false if the edge didn't exist.
+    pub fn set_edge_values(
+        &mut self,
+        node1: usize,
+        node2: usize,
+        values: BTreeMap<String, Value>,
+    ) -> bool {
+        let edge = new_edge(node1, node2);
+        if !self.edges.contains(&edge) {
+            return false;
+        }
+        if !values.is_empty() {
+            self.touch_edge(edge);
+            self.edge_values.entry(edge).or_default().extend(values);
+        }
+        true
+    }
+
+    /// Set the given edge as dirty. Returns false if the edge didn't exist.
+    pub fn set_edge_dirty(&mut self, node1: usize, node2: usize) -> bool {
+        self.touch_edge(new_edge(node1, node2));
+        match self.edge_generation.get_mut(&new_edge(node1, node2)) {
+            Some(gen) => {
+                *gen = self.next_generation;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if the edge is dirty, aka the edge's generation is the same as
+    /// self.next_generation
+    pub fn edge_is_dirty(&self, node1: usize, node2: usize) -> bool {
+        match self.edge_generation.get(&new_edge(node1, node2)) {
+            Some(gen) => *gen >= self.next_generation,
+            None => false,
+        }
+    }
 }
 
 impl Graph for DirtyGraph {
@@ -192,7 +417,11 @@ impl Graph for DirtyGraph {
 }
 
 impl AppendableGraph for DirtyGraph {
+    /// Adds a node with the next id that is not in use, so it never fails.
     fn add_node(&mut self) -> Result<usize, Error> {
+        while self.nodes.contains(&self.next_node) {
+            self.next_node += 1;
+        }
         self.add_node_with(self.next_node)?;
 
         self.next_node += 1;
@@ -205,6 +434,7 @@ impl AppendableGraph for DirtyGraph {
             return Err(gamma::graph::Error::DuplicateNode(id));
         }
 
+        self.touch_node(id);
         self.nodes.insert(id);
         self.node_generation.insert(id, self.next_generation);
         self.adjacency.insert(id, vec![]);
@@ -212,10 +442,23 @@ impl AppendableGraph for DirtyGraph {
         Ok(())
     }
 
+    /// Adds a plain edge. Adding an existing edge changes nothing, so it keeps its label and
+    /// doesn't become dirty.
     fn add_edge(&mut self, sid: usize, tid: usize) -> Result<(), Error> {
-        self.edges.insert(new_edge(sid, tid));
+        for id in &[sid, tid] {
+            if !self.nodes.contains(id) {
+                return Err(gamma::graph::Error::MissingNode(*id));
+            }
+        }
+        if self.contains_edge(sid, tid) {
+            return Ok(());
+        }
+        self.touch_edge(new_edge(sid, tid));
         self.edge_generation
-            .insert((sid, tid), self.next_generation);
+            .insert(new_edge(sid, tid), self.next_generation);
+        self.edge_labels
+            .insert(new_edge(sid, tid), (EdgeLabel::Plain, sid));
+        self.edges.insert(new_edge(sid, tid));
         self.add_to_adjacency(sid, tid);
         self.add_to_adjacency(tid, sid);
         Ok(())
@@ -223,8 +466,16 @@ impl AppendableGraph for DirtyGraph {
 }
 
 impl RemovableGraph for DirtyGraph {
+    /// Removes a node with its edges. Nodes that still hang off it lose their ancestor.
     fn remove_node(&mut self, id: usize) -> usize {
         self.remove_edges_with(id);
+        self.touch_node(id);
+        for child in self.get_children(id) {
+            self.remove_ancestor(child);
+        }
+        self.remove_ancestor(id);
+        self.children.remove(&id);
+        self.adjacency.remove(&id);
         self.node_generation.remove(&id);
         match self.nodes.remove(&id) {
             true => 1,
@@ -234,8 +485,16 @@ impl RemovableGraph for DirtyGraph {
 
     fn remove_edge(&mut self, sid: usize, tid: usize) -> usize {
         let edge = new_edge(sid, tid);
+        self.touch_edge(edge);
+        self.edge_generation.remove(&edge);
+        self.edge_labels.remove(&edge);
+        self.edge_values.remove(&edge);
         match self.edges.remove(&edge) {
-            true => 1,
+            true => {
+                self.remove_from_adjacency(sid, tid);
+                self.remove_from_adjacency(tid, sid);
+                1
+            }
             false => 0,
         }
     }
@@ -254,15 +513,115 @@ impl RemovableGraph for DirtyGraph {
             .collect();
         to_remove
             .into_iter()
-            .map(|e| {
-                // First, remove all generations
-                self.edge_generation.remove(&e);
-                // Then remove actual edges.
-                match self.edges.remove(&e) {
-                    true => 1,
-                    false => 0,
-                }
-            })
+            .map(|e| self.remove_edge(e.0, e.1))
             .sum()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generations() {
+        let mut graph = DirtyGraph::default();
+        let a = graph.add_node().unwrap();
+        let b = graph.add_node().unwrap();
+        graph.add_edge(a, b).unwrap();
+        assert!(graph.node_is_dirty(a));
+        assert!(graph.edge_is_dirty(b, a));
+
+        graph.advance_generation();
+        assert!(!graph.node_is_dirty(a));
+        assert!(!graph.edge_is_dirty(a, b));
+
+        assert!(graph.set_edge_dirty(b, a));
+        assert!(graph.edge_is_dirty(a, b));
+        assert!(!graph.node_is_dirty(b));
+        assert!(!graph.set_edge_dirty(a, a));
+
+        // Adding an edge that exists doesn't touch it
+        graph.advance_generation();
+        graph.add_edge(a, b).unwrap();
+        assert!(!graph.edge_is_dirty(a, b));
+        // Nodes must exist to be connected
+        let missing = graph.add_node().unwrap() + 1;
+        assert!(graph.add_edge(a, missing).is_err());
+        assert!(graph.add_edge(missing, a).is_err());
+        assert!(!graph.adjacency.contains_key(&missing));
+        assert_eq!(graph.size(), 1);
+    }
+
+    #[test]
+    fn test_edge_labels() {
+        let mut graph = DirtyGraph::default();
+        let a = graph.add_node().unwrap();
+        let b = graph.add_node().unwrap();
+        let c = graph.add_node().unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_labeled_edge(c, b, EdgeLabel::Branch).unwrap();
+        assert_eq!(graph.edge_label(b, a), Some(EdgeLabel::Plain));
+        assert!(graph.has_labeled_edge(b, a, EdgeLabel::Plain));
+        assert_eq!(graph.edge_source(a, b), None);
+        assert_eq!(graph.edge_source(b, c), Some(c));
+        assert!(graph.has_labeled_edge(c, b, EdgeLabel::Branch));
+        assert!(!graph.has_labeled_edge(b, c, EdgeLabel::Branch));
+        assert!(!graph.has_labeled_edge(c, b, EdgeLabel::Successor));
+
+        // Adding a plain edge doesn't drop the label, relabeling does
+        graph.add_edge(b, c).unwrap();
+        assert_eq!(graph.edge_label(b, c), Some(EdgeLabel::Branch));
+        graph.add_labeled_edge(b, c, EdgeLabel::Successor).unwrap();
+        assert_eq!(graph.edge_source(b, c), Some(b));
+
+        graph.remove_node(c);
+        assert_eq!(graph.edge_label(b, c), None);
+    }
+
+    #[test]
+    fn test_edge_values() {
+        let mut graph = DirtyGraph::default();
+        let a = graph.add_node().unwrap();
+        let b = graph.add_node().unwrap();
+        assert!(!graph.set_edge_values(a, b, maplit::btreemap! {"angle".to_string() => 1.into()}));
+        graph.add_edge(a, b).unwrap();
+        assert_eq!(graph.edge_values(a, b), None);
+        assert!(graph.set_edge_values(a, b, maplit::btreemap! {"angle".to_string() => 1.into()}));
+        assert!(graph.set_edge_values(
+            b,
+            a,
+            maplit::btreemap! {"stiffness".to_string() => 2.into()}
+        ));
+        let values = graph.edge_values(b, a).unwrap();
+        assert_eq!(values["angle"], Value::Int(1));
+        assert_eq!(values["stiffness"], Value::Int(2));
+
+        graph.remove_edge(a, b);
+        graph.add_edge(a, b).unwrap();
+        assert_eq!(graph.edge_values(a, b), None);
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let mut graph = DirtyGraph::default();
+        let a = graph.add_node().unwrap();
+        let b = graph.add_node().unwrap();
+        let c = graph.add_node().unwrap();
+        graph.add_ancestor(c, a);
+        assert_eq!(graph.get_children(a), vec![c]);
+        // Moving a node to another ancestor removes it from the old one's children
+        graph.add_ancestor(c, b);
+        assert_eq!(graph.get_ancestor(c), Some(b));
+        assert!(graph.get_children(a).is_empty());
+        assert_eq!(graph.get_children(b), vec![c]);
+        graph.remove_ancestor(c);
+        assert_eq!(graph.get_ancestor(c), None);
+        assert!(graph.get_children(b).is_empty());
+        // Removing a node leaves no links to it behind
+        graph.add_ancestor(b, a);
+        graph.add_ancestor(c, b);
+        graph.remove_node(b);
+        assert!(graph.get_children(a).is_empty());
+        assert_eq!(graph.get_ancestor(c), None);
+    }
+}
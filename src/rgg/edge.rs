@@ -0,0 +1,94 @@
+// Edge types, after the successor, branch and refinement edges of relational growth grammars.
+use crate::rgg::{Condition, ValueExpr};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The type of an edge. All types except plain edges are directed, from parent to child.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeLabel {
+    /// An undirected edge without a type.
+    Plain,
+    /// `<`: the next segment along the same axis.
+    #[serde(alias = "<")]
+    Successor,
+    /// `+`: the first segment of a side branch.
+    #[serde(alias = "+")]
+    Branch,
+    /// `/`: a finer-grained part of the parent.
+    #[serde(alias = "/")]
+    Refinement,
+}
+
+impl EdgeLabel {
+    pub fn is_directed(self) -> bool {
+        self != EdgeLabel::Plain
+    }
+
+    /// The symbol used for the label in the literature.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            EdgeLabel::Plain => "-",
+            EdgeLabel::Successor => "<",
+            EdgeLabel::Branch => "+",
+            EdgeLabel::Refinement => "/",
+        }
+    }
+}
+
+impl Default for EdgeLabel {
+    fn default() -> Self {
+        EdgeLabel::Plain
+    }
+}
+
+/// An edge of a rule's pattern, written as `[from, to]`, `[from, to, label]` or as a map that may
+/// also have conditions on the edge's values.
+/// Without a label, any edge between the two nodes matches regardless of its type and direction.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PatternEdge {
+    pub from: i32,
+    pub to: i32,
+    #[serde(default)]
+    pub label: Option<EdgeLabel>,
+    #[serde(default)]
+    pub values: BTreeMap<String, Condition>,
+}
+
+impl PatternEdge {
+    /// Whether matching the edge takes more than the edge existing.
+    pub fn is_constrained(&self) -> bool {
+        self.label.is_some() || !self.values.is_empty()
+    }
+}
+
+impl From<(i32, i32)> for PatternEdge {
+    fn from((from, to): (i32, i32)) -> Self {
+        Self {
+            from,
+            to,
+            label: None,
+            values: Default::default(),
+        }
+    }
+}
+
+/// A matched node that a new node is connected to, written as `id`, `[id, label]` or as a map that
+/// may also have values for the new edge. Directed edges go from the matched node to the new node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighbor {
+    pub id: i32,
+    pub label: EdgeLabel,
+    /// Evaluated like the values of the new node, without a base node.
+    pub values: BTreeMap<String, ValueExpr>,
+}
+
+impl From<i32> for Neighbor {
+    fn from(id: i32) -> Self {
+        Self {
+            id,
+            label: EdgeLabel::Plain,
+            values: Default::default(),
+        }
+    }
+}
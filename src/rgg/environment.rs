@@ -0,0 +1,20 @@
+// State of the simulation outside of the graph, which rules can refer to.
+use crate::rgg::expr::Variables;
+
+/// Passed along when applying rules, so expressions can use it.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// The number of the step being applied, starting at 1.
+    pub generation: u64,
+    /// Named numbers for tuning a grammar, available to expressions and conditions.
+    pub parameters: Variables,
+}
+
+impl Environment {
+    /// Make the environment available to expressions.
+    /// Parameters are available by name, except where they clash with `generation`.
+    pub fn add_variables(&self, variables: &mut Variables) {
+        variables.extend(self.parameters.iter().map(|(k, v)| (k.clone(), *v)));
+        variables.insert("generation".to_string(), self.generation as f64);
+    }
+}
@@ -0,0 +1,68 @@
+use crate::rgg::node::ExprError;
+use std::fmt::{Display, Formatter};
+
+/// Everything that can go wrong while loading, matching or applying rules.
+#[derive(Debug)]
+pub enum RggError {
+    /// A rule could not be read.
+    Parse(String),
+    /// A rule is malformed, e.g. one of its edges refers to a node that it doesn't have.
+    Invalid(String),
+    /// A value of a new or changed node or edge could not be computed.
+    Expr(ExprError),
+    /// A rule node id is not mapped to a node of the graph, or that node no longer exists.
+    MissingNode(i32),
+    /// There is no edge between the nodes that the rule node ids are mapped to.
+    MissingEdge(i32, i32),
+    /// There already is an edge between the nodes that the rule node ids are mapped to.
+    DuplicateEdge(i32, i32),
+    /// The graph refused a change.
+    Graph(gamma::graph::Error),
+}
+
+impl Display for RggError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RggError::Parse(message) => write!(f, "Could not read rule: {}", message),
+            RggError::Invalid(message) => write!(f, "Invalid rule: {}", message),
+            RggError::Expr(error) => error.fmt(f),
+            RggError::MissingNode(id) => write!(f, "Rule node {} is not in the graph", id),
+            RggError::MissingEdge(from, to) => {
+                write!(f, "There is no edge between rule nodes {} and {}", from, to)
+            }
+            RggError::DuplicateEdge(from, to) => write!(
+                f,
+                "There already is an edge between rule nodes {} and {}",
+                from, to
+            ),
+            RggError::Graph(error) => write!(f, "Could not change the graph: {:?}", error),
+        }
+    }
+}
+
+impl std::error::Error for RggError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RggError::Expr(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExprError> for RggError {
+    fn from(error: ExprError) -> Self {
+        RggError::Expr(error)
+    }
+}
+
+impl From<gamma::graph::Error> for RggError {
+    fn from(error: gamma::graph::Error) -> Self {
+        RggError::Graph(error)
+    }
+}
+
+impl From<serde_yaml::Error> for RggError {
+    fn from(error: serde_yaml::Error) -> Self {
+        RggError::Parse(error.to_string())
+    }
+}
@@ -0,0 +1,316 @@
+// The expression language used by rule guards and ToNode values.
+// Arithmetic is handled by meval. On top of that, boolean expressions support comparisons
+// (<, <=, >, >=, ==, !=), &&, || and ! and parentheses, with `true` being any nonzero number.
+// Values of matched nodes are referred to as `n<rule id>.<value name>`, e.g. `n0.len`.
+use crate::rgg::Value;
+use meval::Context;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Named numbers that expressions can refer to.
+pub type Variables = BTreeMap<String, f64>;
+
+/// The name under which a value of a matched node is available to meval, which doesn't allow dots.
+pub fn reference_name(rule_id: i32, value: &str) -> String {
+    format!("n{}__{}", rule_id, value)
+}
+
+/// The rule id and value name of a name created by `reference_name`.
+pub fn parse_reference(name: &str) -> Option<(i32, &str)> {
+    let rest = name.strip_prefix('n')?;
+    let split = rest.find("__")?;
+    let rule_id = rest[..split].parse().ok()?;
+    Some((rule_id, &rest[split + 2..]))
+}
+
+/// Replace `n0.len` style references with the names they are available under.
+pub fn rewrite_references(expr: &str) -> String {
+    let chars = expr.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(expr.len());
+    let mut i = 0;
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_ascii_digit() {
+            // Numbers may contain dots and exponents, so copy them verbatim
+            while i < chars.len() && (is_ident(chars[i]) || chars[i] == '.') {
+                out.push(chars[i]);
+                i += 1;
+            }
+        } else if is_ident(c) {
+            let start = i;
+            while i < chars.len() && is_ident(chars[i]) {
+                i += 1;
+            }
+            out.extend(&chars[start..i]);
+            let followed_by_name = i + 1 < chars.len()
+                && chars[i] == '.'
+                && (chars[i + 1].is_ascii_alphabetic() || chars[i + 1] == '_');
+            if followed_by_name {
+                out.push_str("__");
+                i += 1;
+            }
+        } else {
+            out.push(c);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// The variables an expression refers to, under the names they are available as.
+/// Names that are called, like `rand` in `rand(0, 1)`, are functions and not included.
+pub fn variable_names(expr: &str) -> BTreeSet<String> {
+    let expr = rewrite_references(expr);
+    let chars = expr.chars().collect::<Vec<_>>();
+    let mut names = BTreeSet::new();
+    let mut i = 0;
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    while i < chars.len() {
+        if chars[i].is_ascii_digit() {
+            while i < chars.len() && (is_ident(chars[i]) || chars[i] == '.') {
+                i += 1;
+            }
+        } else if is_ident(chars[i]) {
+            let start = i;
+            while i < chars.len() && is_ident(chars[i]) {
+                i += 1;
+            }
+            let called = chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&'(');
+            if !called {
+                names.insert(chars[start..i].iter().collect());
+            }
+        } else {
+            i += 1;
+        }
+    }
+    names
+}
+
+/// Check that an expression can be evaluated if all the variables it refers to are defined.
+/// Catches syntax errors and unknown functions.
+pub fn check_syntax(expr: &str, boolean: bool) -> Result<(), meval::Error> {
+    let mut context = Context::new();
+    for name in variable_names(expr) {
+        context.var(name, 1.0);
+    }
+    context.func2("rand", |min, _| min);
+    if boolean {
+        eval_bool(expr, &context).map(|_| ())
+    } else {
+        eval_number(expr, &context).map(|_| ())
+    }
+}
+
+/// Make the numeric and boolean values of a node or edge available to expressions, as references
+/// to the matched node `rule_id` if given, and by their plain names otherwise. Booleans are 1 or 0.
+pub fn add_values(
+    variables: &mut Variables,
+    rule_id: Option<i32>,
+    values: &BTreeMap<String, Value>,
+) {
+    for (name, value) in values {
+        let number = match value {
+            Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            value => value.as_number().ok(),
+        };
+        if let Some(number) = number {
+            let name = match rule_id {
+                Some(rule_id) => reference_name(rule_id, name),
+                None => name.clone(),
+            };
+            variables.insert(name, number);
+        }
+    }
+}
+
+/// Create a context with meval's builtins and the given variables.
+pub fn new_context<'a>(variables: &Variables) -> Context<'a> {
+    let mut context = Context::new();
+    for (name, value) in variables {
+        context.var(name.as_str(), *value);
+    }
+    context
+}
+
+/// Evaluate an arithmetic expression.
+pub fn eval_number(expr: &str, context: &Context) -> Result<f64, meval::Error> {
+    meval::eval_str_with_context(rewrite_references(expr), context)
+}
+
+/// Evaluate a boolean expression.
+pub fn eval_bool(expr: &str, context: &Context) -> Result<bool, meval::Error> {
+    eval_or(&rewrite_references(expr), context)
+}
+
+fn eval_or(expr: &str, context: &Context) -> Result<bool, meval::Error> {
+    let mut result = false;
+    for term in split_top_level(expr, "||") {
+        // Evaluate every term so that errors are not hidden by short-circuiting
+        result |= eval_and(term, context)?;
+    }
+    Ok(result)
+}
+
+fn eval_and(expr: &str, context: &Context) -> Result<bool, meval::Error> {
+    let mut result = true;
+    for term in split_top_level(expr, "&&") {
+        result &= eval_not(term, context)?;
+    }
+    Ok(result)
+}
+
+fn eval_not(expr: &str, context: &Context) -> Result<bool, meval::Error> {
+    let expr = expr.trim();
+    if expr.starts_with('!') && !expr.starts_with("!=") {
+        return Ok(!eval_not(&expr[1..], context)?);
+    }
+    if let Some(inner) = strip_parens(expr) {
+        return eval_or(inner, context);
+    }
+    eval_comparison(expr, context)
+}
+
+fn eval_comparison(expr: &str, context: &Context) -> Result<bool, meval::Error> {
+    let bytes = expr.as_bytes();
+    let mut depth = 0;
+    for i in 0..bytes.len() {
+        match bytes[i] {
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            b'<' | b'>' | b'=' | b'!' if depth == 0 => {
+                let two = bytes.get(i + 1) == Some(&b'=');
+                let (op, len) = match (bytes[i], two) {
+                    (b'<', true) => ("<=", 2),
+                    (b'>', true) => (">=", 2),
+                    (b'=', true) => ("==", 2),
+                    (b'!', true) => ("!=", 2),
+                    (b'<', false) => ("<", 1),
+                    (b'>', false) => (">", 1),
+                    // A lone = or ! is left for meval to complain about
+                    _ => continue,
+                };
+                let lhs = meval::eval_str_with_context(&expr[..i], context)?;
+                let rhs = meval::eval_str_with_context(&expr[i + len..], context)?;
+                return Ok(match op {
+                    "<=" => lhs <= rhs,
+                    ">=" => lhs >= rhs,
+                    "==" => lhs == rhs,
+                    "!=" => lhs != rhs,
+                    "<" => lhs < rhs,
+                    _ => lhs > rhs,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(meval::eval_str_with_context(expr, context)? != 0.0)
+}
+
+/// Split on an operator where it is not inside parentheses.
+fn split_top_level<'a>(expr: &'a str, op: &str) -> Vec<&'a str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+    while i < expr.len() {
+        match expr.as_bytes()[i] {
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            _ if depth == 0 && expr.as_bytes()[i..].starts_with(op.as_bytes()) => {
+                parts.push(&expr[start..i]);
+                i += op.len();
+                start = i;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    parts.push(&expr[start..]);
+    parts
+}
+
+/// If the whole expression is wrapped in a pair of parentheses, return what's inside.
+fn strip_parens(expr: &str) -> Option<&str> {
+    if !expr.starts_with('(') || !expr.ends_with(')') {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                // The opening parenthesis was closed before the end
+                if depth == 0 && i != expr.len() - 1 {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(&expr[1..expr.len() - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgg::Node;
+
+    fn context() -> Context<'static> {
+        let mut variables = Variables::new();
+        let mut node = Node::new("stem");
+        node.values.insert("len".to_string(), Value::Float(2.5));
+        node.values.insert("alive".to_string(), Value::Bool(true));
+        add_values(&mut variables, Some(0), &node.values);
+        node.values.insert("len".to_string(), Value::Int(1));
+        add_values(&mut variables, Some(1), &node.values);
+        new_context(&variables)
+    }
+
+    #[test]
+    fn test_rewrite_references() {
+        assert_eq!(rewrite_references("n0.len + 1.5"), "n0__len + 1.5");
+        assert_eq!(rewrite_references("n12.a_b*2e3"), "n12__a_b*2e3");
+        assert_eq!(rewrite_references("len"), "len");
+    }
+
+    #[test]
+    fn test_variable_names() {
+        let names = variable_names("n0.len * rand(0, max_len) + sqrt (2.5e3 * len) > pi");
+        let expected = ["len", "max_len", "n0__len", "pi"];
+        assert_eq!(names, expected.iter().map(|s| s.to_string()).collect());
+        assert_eq!(parse_reference("n12__a_b"), Some((12, "a_b")));
+        assert_eq!(parse_reference("n_len"), None);
+        assert_eq!(parse_reference("len"), None);
+    }
+
+    #[test]
+    fn test_check_syntax() {
+        assert!(check_syntax("n0.len * rand(0, 1) + sqrt(len)", false).is_ok());
+        assert!(check_syntax("len > 2 && !alive", true).is_ok());
+        assert!(check_syntax("len > 2", false).is_err());
+        assert!(check_syntax("len * (2", false).is_err());
+        assert!(check_syntax("grow(len)", false).is_err());
+    }
+
+    #[test]
+    fn test_eval_number() {
+        assert_eq!(eval_number("n0.len * 2 + n1.len", &context()), Ok(6.0));
+    }
+
+    #[test]
+    fn test_eval_bool() {
+        let context = context();
+        let check = |expr: &str| eval_bool(expr, &context).unwrap();
+        assert!(check("n0.len + n1.len > 3"));
+        assert!(check("n0.len > n1.len && n0.alive"));
+        assert!(!check("n0.len <= n1.len || !n1.alive"));
+        assert!(check("(n0.len + 1) * 2 == 7"));
+        assert!(check("!(n0.len < 1 || n1.len != 1) && (n1.len >= 1)"));
+        assert!(check("n0.len != 0"));
+        assert!(!check("0"));
+        assert!(eval_bool("n2.len > 1", &context).is_err());
+        assert!(eval_bool("(n0.len > 1", &context).is_err());
+    }
+}
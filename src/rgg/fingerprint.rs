@@ -0,0 +1,355 @@
+// Hashing rules by what they do rather than how they are written, so that a plant definition can
+// be recognized regardless of comments, formatting, key order or metadata.
+use crate::rgg::condition::{Bounds, StringPattern};
+use crate::rgg::derivation::ConflictPolicy;
+use crate::rgg::procedures::Procedure;
+use crate::rgg::rgg_graph::Fnv;
+use crate::rgg::rule::Alternative;
+use crate::rgg::value::RGGType;
+use crate::rgg::{
+    Condition, DerivationMode, EdgeLabel, FromNode, Neighbor, NodeSet, Operand, PatternEdge, Phase,
+    Rule, RuleGroup, ToNode, Value, ValueExpr,
+};
+use std::collections::BTreeMap;
+
+/// Feed something into a hash the same way on every platform.
+/// Enum variants are told apart by a tag, and collections by their length.
+pub(crate) trait Fingerprint {
+    fn hash_into(&self, hash: &mut Fnv);
+}
+
+impl Fingerprint for bool {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write(&[*self as u8]);
+    }
+}
+
+impl Fingerprint for i32 {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write(&self.to_le_bytes());
+    }
+}
+
+impl Fingerprint for usize {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write_usize(*self);
+    }
+}
+
+impl Fingerprint for f64 {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write(&self.to_bits().to_le_bytes());
+    }
+}
+
+impl Fingerprint for String {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write_str(self);
+    }
+}
+
+impl Fingerprint for Value {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write_value(self);
+    }
+}
+
+impl<T: Fingerprint> Fingerprint for Option<T> {
+    fn hash_into(&self, hash: &mut Fnv) {
+        match self {
+            Some(value) => {
+                hash.write(&[1]);
+                value.hash_into(hash);
+            }
+            None => hash.write(&[0]),
+        }
+    }
+}
+
+impl<T: Fingerprint> Fingerprint for Vec<T> {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write_usize(self.len());
+        for item in self {
+            item.hash_into(hash);
+        }
+    }
+}
+
+impl<T: Fingerprint> Fingerprint for BTreeMap<String, T> {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write_usize(self.len());
+        for (key, value) in self {
+            hash.write_str(key);
+            value.hash_into(hash);
+        }
+    }
+}
+
+impl Fingerprint for EdgeLabel {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write(&[*self as u8]);
+    }
+}
+
+impl Fingerprint for RGGType {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write(&[*self as u8]);
+    }
+}
+
+impl Fingerprint for DerivationMode {
+    fn hash_into(&self, hash: &mut Fnv) {
+        match self {
+            DerivationMode::Sequential => hash.write(&[0]),
+            DerivationMode::Parallel(policy) => {
+                hash.write(&[1]);
+                policy.hash_into(hash);
+            }
+        }
+    }
+}
+
+impl Fingerprint for ConflictPolicy {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write(&[*self as u8]);
+    }
+}
+
+impl Fingerprint for Phase {
+    fn hash_into(&self, hash: &mut Fnv) {
+        self.name.hash_into(hash);
+        self.groups.hash_into(hash);
+    }
+}
+
+impl Fingerprint for RuleGroup {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write(&[self.mode as u8, self.repeat as u8]);
+        self.max_iterations.hash_into(hash);
+        self.rules.hash_into(hash);
+    }
+}
+
+impl Fingerprint for Rule {
+    fn hash_into(&self, hash: &mut Fnv) {
+        self.from.hash_into(hash);
+        self.to.hash_into(hash);
+        self.probability.hash_into(hash);
+        self.alternatives.hash_into(hash);
+        self.priority.hash_into(hash);
+        self.when.hash_into(hash);
+    }
+}
+
+impl Fingerprint for Alternative {
+    fn hash_into(&self, hash: &mut Fnv) {
+        self.weight.hash_into(hash);
+        self.to.hash_into(hash);
+    }
+}
+
+impl Fingerprint for NodeSet {
+    fn hash_into(&self, hash: &mut Fnv) {
+        self.nodes.hash_into(hash);
+        self.edges.hash_into(hash);
+        self.not.hash_into(hash);
+    }
+}
+
+impl Fingerprint for FromNode {
+    fn hash_into(&self, hash: &mut Fnv) {
+        self.id.hash_into(hash);
+        self.name.hash_into(hash);
+        self.values.hash_into(hash);
+    }
+}
+
+impl Fingerprint for PatternEdge {
+    fn hash_into(&self, hash: &mut Fnv) {
+        self.from.hash_into(hash);
+        self.to.hash_into(hash);
+        self.label.hash_into(hash);
+        self.values.hash_into(hash);
+    }
+}
+
+impl Fingerprint for StringPattern {
+    fn hash_into(&self, hash: &mut Fnv) {
+        let (tag, pattern) = match self {
+            StringPattern::Exact(exact) => (0, exact.as_str()),
+            StringPattern::Glob(glob) => (1, glob.as_str()),
+            StringPattern::Regex(regex) => (2, regex.as_str()),
+        };
+        hash.write(&[tag]);
+        hash.write_str(pattern);
+    }
+}
+
+impl Fingerprint for Operand {
+    fn hash_into(&self, hash: &mut Fnv) {
+        match self {
+            Operand::Value(value) => {
+                hash.write(&[0]);
+                value.hash_into(hash);
+            }
+            Operand::Param(name) => {
+                hash.write(&[1]);
+                name.hash_into(hash);
+            }
+        }
+    }
+}
+
+impl Fingerprint for Bounds {
+    fn hash_into(&self, hash: &mut Fnv) {
+        hash.write(&[self.low_inclusive as u8, self.high_inclusive as u8]);
+    }
+}
+
+impl Fingerprint for Condition {
+    fn hash_into(&self, hash: &mut Fnv) {
+        match self {
+            Condition::Equals(operand) => {
+                hash.write(&[0]);
+                operand.hash_into(hash);
+            }
+            Condition::NotEquals(operand) => {
+                hash.write(&[1]);
+                operand.hash_into(hash);
+            }
+            Condition::LessThan(operand) => {
+                hash.write(&[2]);
+                operand.hash_into(hash);
+            }
+            Condition::GreaterThan(operand) => {
+                hash.write(&[3]);
+                operand.hash_into(hash);
+            }
+            Condition::LessThanOrEquals(operand) => {
+                hash.write(&[4]);
+                operand.hash_into(hash);
+            }
+            Condition::GreaterThanOrEquals(operand) => {
+                hash.write(&[5]);
+                operand.hash_into(hash);
+            }
+            Condition::Range(low, high, bounds) => {
+                hash.write(&[6]);
+                low.hash_into(hash);
+                high.hash_into(hash);
+                bounds.hash_into(hash);
+            }
+            Condition::In(operands) => {
+                hash.write(&[7]);
+                operands.hash_into(hash);
+            }
+            Condition::Matches(pattern) => {
+                hash.write(&[8]);
+                pattern.hash_into(hash);
+            }
+            Condition::Exists => hash.write(&[9]),
+            Condition::Missing => hash.write(&[10]),
+            Condition::And(conditions) => {
+                hash.write(&[11]);
+                conditions.hash_into(hash);
+            }
+            Condition::Or(conditions) => {
+                hash.write(&[12]);
+                conditions.hash_into(hash);
+            }
+            Condition::Not(condition) => {
+                hash.write(&[13]);
+                condition.hash_into(hash);
+            }
+        }
+    }
+}
+
+impl Fingerprint for ValueExpr {
+    fn hash_into(&self, hash: &mut Fnv) {
+        match self {
+            ValueExpr::Literal(value) => {
+                hash.write(&[0]);
+                value.hash_into(hash);
+            }
+            ValueExpr::Expr { expr, rgg_type } => {
+                hash.write(&[1]);
+                expr.hash_into(hash);
+                rgg_type.hash_into(hash);
+            }
+        }
+    }
+}
+
+impl Fingerprint for ToNode {
+    fn hash_into(&self, hash: &mut Fnv) {
+        self.name.hash_into(hash);
+        self.values.hash_into(hash);
+    }
+}
+
+impl Fingerprint for Neighbor {
+    fn hash_into(&self, hash: &mut Fnv) {
+        self.id.hash_into(hash);
+        self.label.hash_into(hash);
+        self.values.hash_into(hash);
+    }
+}
+
+impl Fingerprint for Procedure {
+    fn hash_into(&self, hash: &mut Fnv) {
+        match self {
+            Procedure::Delete(proc) => {
+                hash.write(&[0]);
+                proc.target.hash_into(hash);
+            }
+            Procedure::Replace(proc) => {
+                hash.write(&[1]);
+                proc.target.hash_into(hash);
+                proc.replacement.hash_into(hash);
+            }
+            Procedure::Add(proc) => {
+                hash.write(&[2]);
+                proc.neighbors.hash_into(hash);
+                proc.new_node.hash_into(hash);
+            }
+            Procedure::Merge(proc) => {
+                hash.write(&[3]);
+                proc.targets.hash_into(hash);
+                proc.final_node.hash_into(hash);
+            }
+            Procedure::SetEdge(proc) => {
+                hash.write(&[4]);
+                proc.from.hash_into(hash);
+                proc.to.hash_into(hash);
+                proc.label.hash_into(hash);
+                proc.values.hash_into(hash);
+            }
+            Procedure::Connect(proc) => {
+                hash.write(&[5]);
+                proc.from.hash_into(hash);
+                proc.to.hash_into(hash);
+                proc.label.hash_into(hash);
+                proc.values.hash_into(hash);
+            }
+            Procedure::Disconnect(proc) => {
+                hash.write(&[6]);
+                proc.from.hash_into(hash);
+                proc.to.hash_into(hash);
+            }
+            Procedure::Split(proc) => {
+                hash.write(&[7]);
+                proc.from.hash_into(hash);
+                proc.to.hash_into(hash);
+                proc.new_node.hash_into(hash);
+                proc.label.hash_into(hash);
+            }
+            Procedure::MoveEdge(proc) => {
+                hash.write(&[8]);
+                for id in &[proc.edge.0, proc.edge.1, proc.to.0, proc.to.1] {
+                    id.hash_into(hash);
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,18 @@
 use crate::rgg::rgg_graph::RggGraph;
-use crate::rgg::rule::Rule;
-use gamma::graph::{DefaultGraph, Graph};
-use std::collections::HashMap;
+use crate::rgg::rule::{NodeSet, Rule};
+use crate::rgg::{Environment, FromNode, PatternEdge, RggError};
+use gamma::graph::Graph;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 
 impl Rule {
-    pub fn matches<'a>(&'a self, graph: &'a RggGraph) -> MatchingState<'a> {
-        MatchingState::new(&self, graph)
+    /// Find all matches of the rule's pattern. Fails if the pattern is malformed or refers to
+    /// parameters that don't exist.
+    pub fn matches<'a>(
+        &'a self,
+        graph: &'a RggGraph,
+        env: &'a Environment,
+    ) -> Result<MatchingState<'a>, RggError> {
+        MatchingState::new(self, graph, env)
     }
 }
 
@@ -15,126 +22,403 @@ pub enum MatchingDecision {
     Mapped,
 }
 
+/// A node of the pattern that needs to be assigned a host node.
+struct Slot<'a> {
+    /// The rule node id
+    id: i32,
+    /// The conditions on the node. Nodes that are only referred to by edges have none.
+    node: Option<&'a FromNode>,
+}
+
 /// Hold state for matching function.
+/// Performs a depth-first search in the style of VF2: pattern nodes are assigned in an order
+/// where every node (except the first of each connected component) is adjacent to an
+/// already-assigned node, so candidates only need to be drawn from that node's neighbors.
 pub struct MatchingState<'a> {
     graph: &'a RggGraph,
-    rule: &'a Rule,
-    /// The necessary relations (between rule nodes) for a match.
-    /// Uses the rule node id.
-    relations: DefaultGraph,
+    pattern: &'a NodeSet,
+    /// Holds the parameters that conditions may refer to.
+    env: &'a Environment,
     /// Discovered mappings between rule node ids and Rgg ids.
     mapping: HashMap<i32, usize>,
-    /// The current pattern node we are checking against.
-    pattern_index: i32,
-    /// Store the last node up to which we have checked for a given rule node.
-    progress: HashMap<usize, usize>,
-    /// Indexable copy of the graph nodes
-    graph_nodes: Vec<usize>,
+    /// The position in `order` we are currently trying to assign.
+    pattern_index: usize,
+    /// Positions before this one were assigned up front and are never changed.
+    first_free: usize,
+    /// Set once the search can not produce any more matches.
+    exhausted: bool,
+    /// Every node of the pattern.
+    slots: Vec<Slot<'a>>,
+    /// The order in which pattern nodes are assigned, as indices into slots.
+    order: Vec<usize>,
+    /// For every position, an earlier position whose host node's neighbors are the candidates.
+    /// None means all graph nodes are candidates.
+    anchors: Vec<Option<usize>>,
+    /// For every position, the earlier positions that the pattern node has an edge to.
+    back_edges: Vec<Vec<usize>>,
+    /// For every position, the pattern edges to earlier positions that have a label or conditions:
+    /// the earlier position, the edge, and whether the edge starts at this position.
+    constrained_back_edges: Vec<Vec<(usize, &'a PatternEdge, bool)>>,
+    /// For every position, the number of edges the pattern node has.
+    degrees: Vec<usize>,
+    /// For every position, the host nodes that could be assigned to it.
+    candidates: Vec<Vec<usize>>,
+    /// For every position, the index into candidates from which to continue scanning.
+    progress: Vec<usize>,
+    /// For every position, the host node currently assigned to it.
+    assigned: Vec<Option<usize>>,
+    /// All host nodes that are currently assigned, so no host node is used twice.
+    used: HashSet<usize>,
+    /// Whether nodes and edges that were changed during the current generation are left out.
+    skip_dirty: bool,
 }
 
 impl<'a> MatchingState<'a> {
-    pub fn new(rule: &'a Rule, graph: &'a RggGraph) -> Self {
-        let relations = rule.from.as_graph();
+    pub fn new(
+        rule: &'a Rule,
+        graph: &'a RggGraph,
+        env: &'a Environment,
+    ) -> Result<Self, RggError> {
+        rule.from.check_edges(&BTreeSet::new())?;
+        rule.from.check_parameters(&env.parameters)?;
+        Self::with_fixed(&rule.from, graph, env, &HashMap::new(), true)
+    }
 
-        Self {
+    /// Match a pattern where some of the rule node ids are already mapped to host nodes.
+    /// The fixed nodes may be used in the pattern's edges without being listed in its nodes,
+    /// and no other pattern node will be mapped to any of them.
+    /// If `skip_dirty` is set, nodes and edges that were changed this generation can't be matched.
+    /// Fails if an edge of the pattern refers to a node that is neither in the pattern nor fixed.
+    pub fn with_fixed(
+        pattern: &'a NodeSet,
+        graph: &'a RggGraph,
+        env: &'a Environment,
+        fixed: &HashMap<i32, usize>,
+        skip_dirty: bool,
+    ) -> Result<Self, RggError> {
+        let mut state = Self {
             graph,
-            rule,
-            relations,
+            pattern,
+            env,
             mapping: HashMap::new(),
             pattern_index: 0,
-            progress: maplit::hashmap! { 0 => 0 },
-            graph_nodes: graph.graph.nodes().copied().collect(),
+            first_free: 0,
+            exhausted: false,
+            slots: vec![],
+            order: vec![],
+            anchors: vec![],
+            back_edges: vec![],
+            constrained_back_edges: vec![],
+            degrees: vec![],
+            candidates: vec![],
+            progress: vec![],
+            assigned: vec![],
+            used: HashSet::new(),
+            skip_dirty,
+        };
+
+        // Fixed nodes that the pattern refers to come first, then the nodes to search for.
+        let mut fixed_ids = BTreeSet::new();
+        for node in &pattern.nodes {
+            if fixed.contains_key(&node.id) {
+                fixed_ids.insert(node.id);
+            }
+        }
+        for edge in &pattern.edges {
+            for id in &[edge.from, edge.to] {
+                if fixed.contains_key(id) {
+                    fixed_ids.insert(*id);
+                }
+            }
+        }
+        let find_node = |id: i32| pattern.nodes.iter().find(|node| node.id == id);
+        for id in &fixed_ids {
+            state.slots.push(Slot {
+                id: *id,
+                node: find_node(*id),
+            });
+        }
+        for node in &pattern.nodes {
+            if !fixed.contains_key(&node.id) {
+                state.slots.push(Slot {
+                    id: node.id,
+                    node: Some(node),
+                });
+            }
+        }
+        let slot_count = state.slots.len();
+
+        // Pattern adjacency, by index into slots
+        let mut adjacency = vec![BTreeSet::new(); slot_count];
+        let index_of = |slots: &[Slot], id: i32| slots.iter().position(|slot| slot.id == id);
+        for edge in &pattern.edges {
+            match (
+                index_of(&state.slots, edge.from),
+                index_of(&state.slots, edge.to),
+            ) {
+                (Some(from), Some(to)) => {
+                    adjacency[from].insert(to);
+                    adjacency[to].insert(from);
+                }
+                _ => {
+                    return Err(RggError::Invalid(format!(
+                        "Edge ({}, {}) refers to a node that is not in the pattern",
+                        edge.from, edge.to
+                    )))
+                }
+            }
+        }
+
+        // Place the fixed nodes, then do a breadth-first ordering of each connected component of the pattern.
+        let fixed_count = fixed_ids.len();
+        let mut position: Vec<Option<usize>> = vec![None; slot_count];
+        let mut queued = vec![false; slot_count];
+        let mut queue: VecDeque<(usize, Option<usize>)> =
+            (0..fixed_count).map(|index| (index, None)).collect();
+        queued[..fixed_count].iter_mut().for_each(|q| *q = true);
+        loop {
+            let (index, anchor) = match queue.pop_front() {
+                Some(next) => next,
+                // Start on the next connected component
+                None => match (0..slot_count).find(|index| !queued[*index]) {
+                    Some(root) => {
+                        queued[root] = true;
+                        (root, None)
+                    }
+                    None => break,
+                },
+            };
+            let me = state.order.len();
+            position[index] = Some(me);
+            state.order.push(index);
+            state.anchors.push(anchor);
+            state.degrees.push(adjacency[index].len());
+            state.back_edges.push(
+                adjacency[index]
+                    .iter()
+                    .filter_map(|n| position[*n])
+                    .filter(|p| *p < me)
+                    .collect(),
+            );
+            for neighbor in &adjacency[index] {
+                if !queued[*neighbor] {
+                    queued[*neighbor] = true;
+                    queue.push_back((*neighbor, Some(me)));
+                }
+            }
+        }
+
+        // Labels and values of edges are checked once both ends are assigned
+        state.constrained_back_edges = vec![vec![]; slot_count];
+        for edge in pattern.edges.iter().filter(|edge| edge.is_constrained()) {
+            let position_of = |id| index_of(&state.slots, id).and_then(|index| position[index]);
+            if let (Some(from), Some(to)) = (position_of(edge.from), position_of(edge.to)) {
+                if from > to {
+                    state.constrained_back_edges[from].push((to, edge, true));
+                } else {
+                    state.constrained_back_edges[to].push((from, edge, false));
+                }
+            }
+        }
+
+        state.candidates = vec![vec![]; slot_count];
+        state.progress = vec![0; slot_count];
+        state.assigned = vec![None; slot_count];
+
+        // Assign the fixed nodes, checking everything that would otherwise be checked during search.
+        state.first_free = fixed_count;
+        for index in 0..state.first_free {
+            let host = fixed[&state.slots[state.order[index]].id];
+            if !state.is_feasible(index, host) {
+                state.exhausted = true;
+                return Ok(state);
+            }
+            state.assign(index, host);
         }
+        // Fixed nodes that this pattern doesn't mention are still taken.
+        state.used.extend(fixed.values().copied());
+        state.pattern_index = state.first_free;
+        if state.pattern_index < slot_count {
+            state.candidates[state.pattern_index] = state.find_candidates(state.pattern_index);
+        }
+        Ok(state)
     }
 
-    fn get_rule_id(&self, rules: &Rule) -> i32 {
-        rules.from.nodes[self.pattern_index as usize].id
+    fn get_rule_id(&self) -> i32 {
+        self.slots[self.order[self.pattern_index]].id
     }
 
-    /// Find the next tentative match (disregarding edge relations)
-    pub fn continue_search(&mut self, rules: &Rule, graph: &RggGraph) -> MatchingDecision {
-        log::debug!("Continuing search...");
-        // Exit condition 1: We have found a match that needs to be handled.
-        if rules.from.nodes.len() <= self.pattern_index as usize {
-            log::debug!("All {} rules were matched.", rules.from.nodes.len());
-            return MatchingDecision::Mapped;
+    /// All host nodes that could be assigned to the given position, given the earlier assignments.
+    fn find_candidates(&self, index: usize) -> Vec<usize> {
+        match self.anchors[index].and_then(|anchor| self.assigned[anchor]) {
+            Some(host) => match self.graph.neighbors(host) {
+                Ok(neighbors) => neighbors.copied().collect(),
+                Err(_) => vec![],
+            },
+            None => self.graph.graph.nodes().copied().collect(),
         }
-        // Exit condition 2: There are no matches at all
-        if self.progress[&0] >= graph.graph.order() {
-            log::debug!("Reached end of graph. Exiting.");
-            return MatchingDecision::NoMatch;
+    }
+
+    /// Check whether a host node can be assigned to the pattern node at the given position.
+    /// Cheap structural checks are done first, and value conditions last.
+    fn is_feasible(&self, index: usize, host: usize) -> bool {
+        if self.used.contains(&host) || (self.skip_dirty && self.graph.graph.node_is_dirty(host)) {
+            return false;
+        }
+        let pattern_node = self.slots[self.order[index]].node;
+        let node = match self.graph.values.get(&host) {
+            Some(node) => node,
+            None => return false,
+        };
+        if let Some(pattern_node) = pattern_node {
+            if !pattern_node.match_name(node) {
+                return false;
+            }
         }
-        // Scan graph nodes until we find a match.
-        let start = *self
-            .progress
-            .entry(self.pattern_index as usize)
-            .or_insert(0);
-        let end = self.graph_nodes.len();
-
-        let rule_id = self.get_rule_id(rules);
-        log::debug!("Scanning for rule {}: {} to {}", rule_id, start, end);
-        for i in start..end {
-            // Skip if already matched.
-            if self.mapping.contains_key(&rule_id) {
-                log::debug!("Skipping rule {} because it was already matched", rule_id);
-                continue;
+        match self.graph.graph.degree(host) {
+            Ok(degree) if degree >= self.degrees[index] => {}
+            _ => return false,
+        }
+        for earlier in &self.back_edges[index] {
+            let other = match self.assigned[*earlier] {
+                Some(other) => other,
+                None => return false,
+            };
+            if !self.graph.graph.has_edge(host, other).unwrap_or(false)
+                || (self.skip_dirty && self.graph.graph.edge_is_dirty(host, other))
+            {
+                return false;
+            }
+        }
+        for (earlier, edge, starts_here) in &self.constrained_back_edges[index] {
+            let other = match self.assigned[*earlier] {
+                Some(other) => other,
+                None => return false,
+            };
+            let (from, to) = if *starts_here {
+                (host, other)
+            } else {
+                (other, host)
+            };
+            if !self.match_edge(edge, from, to) {
+                return false;
             }
-            if rules.from.nodes[self.pattern_index as usize].match_node(&graph.values[&i]) {
-                // Add it as a tentative match
-                log::debug!(
-                    "Inserting tentative match {}->{}",
-                    rule_id,
-                    self.graph_nodes[i]
-                );
-                self.mapping.insert(rule_id, self.graph_nodes[i]);
-                // Bookmark progress
-                self.progress.insert(self.pattern_index as usize, i + 1);
-                log::debug!("Bookmarked {}->{}", self.pattern_index, i + 1);
+        }
+
+        match pattern_node {
+            Some(pattern_node) => pattern_node.match_node(node, &self.env.parameters),
+            None => true,
+        }
+    }
+
+    /// Check the label and values of the host edge between `from` and `to`.
+    fn match_edge(&self, edge: &PatternEdge, from: usize, to: usize) -> bool {
+        if let Some(label) = edge.label {
+            if !self.graph.graph.has_labeled_edge(from, to, label) {
+                return false;
+            }
+        }
+        let values = self.graph.graph.edge_values(from, to);
+        edge.values.iter().all(|(name, condition)| {
+            condition.check(
+                values.and_then(|values| values.get(name)),
+                &self.env.parameters,
+            )
+        })
+    }
+
+    fn assign(&mut self, index: usize, host: usize) {
+        let rule_id = self.slots[self.order[index]].id;
+        self.mapping.insert(rule_id, host);
+        self.assigned[index] = Some(host);
+        self.used.insert(host);
+    }
+
+    /// Undo the assignment at the current position, if any.
+    fn unassign(&mut self) {
+        if let Some(host) = self.assigned[self.pattern_index].take() {
+            self.used.remove(&host);
+            let rule_id = self.get_rule_id();
+            self.mapping.remove(&rule_id);
+        }
+    }
+
+    /// Assign the next feasible candidate to the current position, or backtrack if there is none.
+    pub fn continue_search(&mut self) -> MatchingDecision {
+        if self.exhausted {
+            return MatchingDecision::NoMatch;
+        }
+        // Exit condition 1: We have found a match that needs to be handled.
+        if self.order.len() <= self.pattern_index {
+            log::debug!("All {} rules were matched.", self.order.len());
+            return MatchingDecision::Mapped;
+        }
+        self.unassign();
+
+        let index = self.pattern_index;
+        for i in self.progress[index]..self.candidates[index].len() {
+            let host = self.candidates[index][i];
+            if self.is_feasible(index, host) {
+                log::debug!("Inserting tentative match {}->{}", self.get_rule_id(), host);
+                self.assign(index, host);
+                self.progress[index] = i + 1;
                 // Look for next rule.
                 self.pattern_index += 1;
+                if self.pattern_index < self.order.len() {
+                    self.candidates[self.pattern_index] = self.find_candidates(self.pattern_index);
+                    self.progress[self.pattern_index] = 0;
+                }
                 return MatchingDecision::Continue;
             }
         }
 
-        // If we're on pattern index 0 and we're out of things, we're done here
-        if self.pattern_index == 0 {
+        // Exit condition 2: The first pattern node has run out of candidates, so there are no more matches.
+        if index == self.first_free {
+            log::debug!("Reached end of graph. Exiting.");
+            self.exhausted = true;
             return MatchingDecision::NoMatch;
         }
 
-        // If failed to find next match, decrement the pattern_index, clearing out any bookmarks and mappings.
-        self.mapping.remove(&rule_id);
-        self.progress.remove(&(self.pattern_index as usize));
+        // If failed to find next match, go back to the previous pattern node.
         self.pattern_index -= 1;
-
         MatchingDecision::Continue
     }
 
-    /// For the current match, check that it satisfies the edges required.
-    pub fn check_edges(&mut self, rules: &Rule) -> anyhow::Result<bool> {
-        if (self.pattern_index as usize) < rules.from.nodes.len() {
-            panic!("Didn't map all the nodes");
+    /// Once we verify a match, we can reset to find further matches
+    /// by continuing the scan of the last pattern node.
+    pub fn reset_match(&mut self) {
+        if self.pattern_index == self.first_free {
+            // Everything was fixed, so there is exactly one match.
+            self.exhausted = true;
+        } else {
+            self.pattern_index -= 1;
         }
-
-        for edge in rules.from.edges.iter() {
-            // Look up the from/to that we matched to.
-            let from = &self.mapping[&edge.0];
-            let to = &self.mapping[&edge.1];
-            if !self.relations.has_edge(*from, *to)? {
-                log::debug!("({}, {}) not in graph {:?}", edge.0, edge.1, self.relations);
-                return Ok(false);
-            }
-        }
-
-        Ok(true)
     }
 
-    /// Once we verify a match, we can reset to find further matches by removing the mapping.
-    pub fn reset_match(&mut self, rules: &Rule) {
-        self.pattern_index -= 1;
-        let r = self.mapping.remove(&self.get_rule_id(rules));
-        if r.is_none() {
-            log::debug!("Tried to reset but there was nothing to reset.");
+    /// Check that none of the pattern's negative application conditions can be found
+    /// around the current match. Dirty nodes count, since they exist even if they can't be rewritten.
+    /// The patterns were checked when matching started.
+    fn check_forbidden(&self) -> bool {
+        for forbidden in &self.pattern.not {
+            let mut matcher = match MatchingState::with_fixed(
+                forbidden,
+                self.graph,
+                self.env,
+                &self.mapping,
+                false,
+            ) {
+                Ok(matcher) => matcher,
+                Err(e) => {
+                    log::warn!("Skipping negative condition: {}", e);
+                    continue;
+                }
+            };
+            if matcher.next().is_some() {
+                log::debug!("Match {:?} rejected by negative condition", self.mapping);
+                return false;
+            }
         }
+        true
     }
 }
 
@@ -142,31 +426,24 @@ impl Iterator for MatchingState<'_> {
     type Item = HashMap<i32, usize>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        #[allow(unused_assignments)]
-        let mut output = HashMap::new();
+        // A pattern without nodes has nothing to match
+        if self.order.is_empty() {
+            return None;
+        }
         loop {
-            let result = self.continue_search(self.rule, self.graph);
-            match result {
+            match self.continue_search() {
                 MatchingDecision::NoMatch => return None,
                 MatchingDecision::Mapped => {
-                    let result = self.check_edges(self.rule);
-                    match &result {
-                        Err(e) => panic!("{:?}", e),
-                        Ok(r) => {
-                            if *r {
-                                output = self.mapping.clone();
-                                self.reset_match(self.rule);
-                                break;
-                            } else {
-                                self.reset_match(self.rule)
-                            }
-                        }
+                    let allowed = self.check_forbidden();
+                    let output = self.mapping.clone();
+                    self.reset_match();
+                    if allowed {
+                        return Some(output);
                     }
                 }
                 MatchingDecision::Continue => {}
             }
         }
-        Some(output)
     }
 }
 
@@ -174,7 +451,7 @@ impl Iterator for MatchingState<'_> {
 mod tests {
     use super::*;
     use crate::rgg::rule::NodeSet;
-    use crate::rgg::FromNode;
+    use crate::rgg::{EdgeLabel, FromNode, Node};
     use gamma::graph::AppendableGraph;
     use ntest::timeout;
 
@@ -187,9 +464,10 @@ mod tests {
                 values: Default::default(),
             }],
             edges: vec![],
+            not: vec![],
         };
         let to = vec![];
-        Rule { from, to }
+        Rule::new(from, to)
     }
 
     /// This rule takes two generic nodes connected by an edge, and adds a third node.
@@ -207,10 +485,11 @@ mod tests {
                     values: Default::default(),
                 },
             ],
-            edges: vec![(0, 1)],
+            edges: vec![(0, 1).into()],
+            not: vec![],
         };
         let to = vec![];
-        Rule { from, to }
+        Rule::new(from, to)
     }
 
     fn get_test_graph() -> RggGraph {
@@ -218,6 +497,7 @@ mod tests {
         graph.insert_node();
         graph.insert_node();
         graph.graph.add_edge(0, 1).unwrap();
+        graph.graph.advance_generation();
         graph
     }
 
@@ -228,10 +508,10 @@ mod tests {
 
         let rule = get_simple_test_rule();
         let graph = get_test_graph();
-        let mut matcher = MatchingState::new(&rule, &graph);
-        matcher.continue_search(&rule, &graph);
+        let env = Environment::default();
+        let mut matcher = MatchingState::new(&rule, &graph, &env).unwrap();
+        matcher.continue_search();
         assert_eq!(matcher.pattern_index, 1);
-        assert!(matcher.check_edges(&rule).unwrap());
         assert_eq!(matcher.mapping, hashmap! { 0 => 0 });
     }
 
@@ -239,19 +519,16 @@ mod tests {
     #[timeout(500)]
     fn test_match_2() {
         use maplit::hashmap;
-        use simplelog::*;
-
-        TermLogger::init(LevelFilter::Info, Config::default(), TerminalMode::Mixed).unwrap();
 
         let rule = get_test_rule();
         let graph = get_test_graph();
-        let mut matcher = rule.matches(&graph);
+        let env = Environment::default();
+        let mut matcher = rule.matches(&graph, &env).unwrap();
         let matched = matcher.next();
 
         match matched {
             None => panic!("No matches found"),
             Some(m) => {
-                assert_eq!(matcher.pattern_index, 2);
                 assert_eq!(m, hashmap! { 0 => 0, 1 => 1 });
             }
         }
@@ -260,10 +537,386 @@ mod tests {
     #[test]
     #[timeout(500)]
     fn test_match_terminates() {
-        simplelog::SimpleLogger::init(simplelog::LevelFilter::Info, simplelog::Config::default())
-            .unwrap();
         let rule = get_test_rule();
         let graph = get_test_graph();
-        let _v = rule.matches(&graph).collect::<Vec<_>>();
+        let env = Environment::default();
+        let _v = rule.matches(&graph, &env).unwrap().collect::<Vec<_>>();
+    }
+
+    fn rule_from_yaml(yaml: &str) -> Rule {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    /// Collect all matches in a stable order.
+    fn sorted_matches(rule: &Rule, graph: &RggGraph) -> Vec<Vec<(i32, usize)>> {
+        let mut matches = rule
+            .matches(graph, &Environment::default())
+            .unwrap()
+            .map(|m| {
+                let mut m = m.into_iter().collect::<Vec<_>>();
+                m.sort_unstable();
+                m
+            })
+            .collect::<Vec<_>>();
+        matches.sort();
+        matches
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_checks_host_edges() {
+        let rule = get_test_rule();
+        let mut graph = get_test_graph();
+        // A third node that is not connected to anything should never be matched
+        graph.insert_node();
+        graph.graph.advance_generation();
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![vec![(0, 0), (1, 1)], vec![(0, 1), (1, 0)]]
+        );
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_deleted_nodes() {
+        let rule = get_test_rule();
+        let mut graph = RggGraph::new();
+        for _ in 0..4 {
+            graph.insert_node();
+        }
+        graph.graph.add_edge(0, 1).unwrap();
+        graph.graph.add_edge(2, 3).unwrap();
+        graph.remove_node(0);
+        graph.remove_node(1);
+        graph.graph.advance_generation();
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![vec![(0, 2), (1, 3)], vec![(0, 3), (1, 2)]]
+        );
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_triangle() {
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}, {id: 1}, {id: 2}]
+  edges: [[0, 1], [1, 2], [2, 0]]
+to: []"#,
+        );
+        let mut graph = RggGraph::new();
+        for _ in 0..5 {
+            graph.insert_node();
+        }
+        // Triangle 1-2-3, with 0 and 4 hanging off of it
+        for (from, to) in &[(1, 2), (2, 3), (3, 1), (0, 1), (3, 4)] {
+            graph.graph.add_edge(*from, *to).unwrap();
+        }
+        graph.remove_node(0);
+        graph.graph.advance_generation();
+        let matches = sorted_matches(&rule, &graph);
+        assert_eq!(matches.len(), 6, "{:?}", matches);
+        for m in matches {
+            let mut nodes = m.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+            nodes.sort_unstable();
+            assert_eq!(nodes, vec![1, 2, 3]);
+        }
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_names_and_edges() {
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}, {id: 1, name: shoot}]
+  edges: [[0, 1]]
+to: []"#,
+        );
+        let mut graph = RggGraph::new();
+        let stem = graph.insert_node_with(Node::new("stem"));
+        let other_stem = graph.insert_node_with(Node::new("stem"));
+        let shoot = graph.insert_node_with(Node::new("shoot"));
+        graph.graph.add_edge(stem, other_stem).unwrap();
+        graph.graph.add_edge(other_stem, shoot).unwrap();
+        graph.graph.advance_generation();
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![vec![(0, other_stem), (1, shoot)]]
+        );
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_injective() {
+        // A path of three nodes cannot fit onto a single edge
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}, {id: 1}, {id: 2}]
+  edges: [[0, 1], [1, 2]]
+to: []"#,
+        );
+        let graph = get_test_graph();
+        assert!(sorted_matches(&rule, &graph).is_empty());
+
+        // Two unconnected pattern nodes need two different host nodes
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}, {id: 1}]
+to: []"#,
+        );
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![vec![(0, 0), (1, 1)], vec![(0, 1), (1, 0)]]
+        );
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_degree_pruning() {
+        // A node with three neighbors only exists once in a star graph
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}, {id: 1}, {id: 2}, {id: 3}]
+  edges: [[0, 1], [0, 2], [0, 3]]
+to: []"#,
+        );
+        let mut graph = RggGraph::new();
+        let center = graph.insert_node();
+        for _ in 0..3 {
+            let leaf = graph.insert_node();
+            graph.graph.add_edge(center, leaf).unwrap();
+        }
+        graph.graph.advance_generation();
+        let matches = sorted_matches(&rule, &graph);
+        assert_eq!(matches.len(), 6);
+        assert!(matches.iter().all(|m| m[0] == (0, center)));
+    }
+
+    #[test]
+    #[timeout(2000)]
+    fn test_match_large_graph() {
+        // A long chain of stems with a single shoot at the end
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}, {id: 1, name: stem}, {id: 2, name: shoot}]
+  edges: [[0, 1], [1, 2]]
+to: []"#,
+        );
+        let mut graph = RggGraph::new();
+        let mut last = graph.insert_node_with(Node::new("stem"));
+        for _ in 0..5000 {
+            let next = graph.insert_node_with(Node::new("stem"));
+            graph.graph.add_edge(last, next).unwrap();
+            last = next;
+        }
+        let shoot = graph.insert_node_with(Node::new("shoot"));
+        graph.graph.add_edge(last, shoot).unwrap();
+        graph.graph.advance_generation();
+
+        let matches = sorted_matches(&rule, &graph);
+        assert_eq!(matches, vec![vec![(0, last - 1), (1, last), (2, shoot)]]);
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_negative_node() {
+        // A stem without a neighboring shoot
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}]
+  not:
+    - nodes: [{id: 1, name: shoot}]
+      edges: [[0, 1]]
+to: []"#,
+        );
+        let mut graph = RggGraph::new();
+        let bare = graph.insert_node_with(Node::new("stem"));
+        let sprouted = graph.insert_node_with(Node::new("stem"));
+        let shoot = graph.insert_node_with(Node::new("shoot"));
+        graph.graph.add_edge(bare, sprouted).unwrap();
+        graph.graph.add_edge(sprouted, shoot).unwrap();
+        graph.graph.advance_generation();
+        assert_eq!(sorted_matches(&rule, &graph), vec![vec![(0, bare)]]);
+
+        // Once it has a shoot, the rule stops firing, even if the shoot is new
+        let other_shoot = graph.insert_node_with(Node::new("shoot"));
+        graph.graph.add_edge(bare, other_shoot).unwrap();
+        assert!(sorted_matches(&rule, &graph).is_empty());
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_negative_edge() {
+        // Two nodes that are not connected to each other
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}, {id: 1}]
+  not:
+    - edges: [[0, 1]]
+to: []"#,
+        );
+        let mut graph = get_test_graph();
+        let lonely = graph.insert_node();
+        graph.graph.advance_generation();
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![
+                vec![(0, 0), (1, lonely)],
+                vec![(0, 1), (1, lonely)],
+                vec![(0, lonely), (1, 0)],
+                vec![(0, lonely), (1, 1)],
+            ]
+        );
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_negative_conditions() {
+        // Forbidden nodes must not be one of the matched nodes, and may restate matched nodes
+        // to put further conditions on them.
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}, {id: 1, name: stem}]
+  edges: [[0, 1]]
+  not:
+    - nodes: [{id: 2, name: stem}]
+      edges: [[1, 2]]
+    - nodes: [{id: 0, values: {old: [eq, 1]}}]
+to: []"#,
+        );
+        let mut graph = RggGraph::new();
+        let mut old = Node::new("stem");
+        old.values.insert("old".to_string(), 1.into());
+        let base = graph.insert_node_with(old);
+        let middle = graph.insert_node_with(Node::new("stem"));
+        let tip = graph.insert_node_with(Node::new("stem"));
+        graph.graph.add_edge(base, middle).unwrap();
+        graph.graph.add_edge(middle, tip).unwrap();
+        graph.graph.advance_generation();
+        // Node 1 has to be an end of the chain, and node 0 can't be old
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![vec![(0, middle), (1, base)], vec![(0, middle), (1, tip)]]
+        );
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_edge_labels() {
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}, {id: 1}]
+  edges: [[0, 1, branch]]
+to: []"#,
+        );
+        let mut graph = RggGraph::new();
+        let stem = graph.insert_node_with(Node::new("stem"));
+        let shoot = graph.insert_node_with(Node::new("shoot"));
+        let tip = graph.insert_node_with(Node::new("tip"));
+        graph
+            .graph
+            .add_labeled_edge(stem, shoot, EdgeLabel::Branch)
+            .unwrap();
+        graph
+            .graph
+            .add_labeled_edge(stem, tip, EdgeLabel::Successor)
+            .unwrap();
+        graph.graph.advance_generation();
+        // Only in the direction of the edge
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![vec![(0, stem), (1, shoot)]]
+        );
+
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}, {id: 1, name: "tip"}]
+  edges: [[1, 0, "<"]]
+to: []"#,
+        );
+        assert!(sorted_matches(&rule, &graph).is_empty());
+        // Unlabeled edges match edges of any type, in any direction
+        let rule = rule_from_yaml("from: {nodes: [{id: 0}, {id: 1}], edges: [[0, 1]]}\nto: []");
+        assert_eq!(sorted_matches(&rule, &graph).len(), 4);
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_edge_values() {
+        use crate::rgg::Value;
+
+        let rule = rule_from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}, {id: 1}]
+  edges: [{from: 0, to: 1, values: {angle: [gt, 30]}}]
+to: []"#,
+        );
+        let mut graph = RggGraph::new();
+        let stem = graph.insert_node_with(Node::new("stem"));
+        let steep = graph.insert_node_with(Node::new("shoot"));
+        let flat = graph.insert_node_with(Node::new("shoot"));
+        graph
+            .graph
+            .add_labeled_edge(stem, steep, EdgeLabel::Branch)
+            .unwrap();
+        graph.graph.add_edge(stem, flat).unwrap();
+        graph.graph.set_edge_values(
+            stem,
+            steep,
+            maplit::btreemap! {
+                "angle".to_string() => Value::Float(45.0),
+            },
+        );
+        graph.graph.set_edge_values(
+            stem,
+            flat,
+            maplit::btreemap! {
+                "angle".to_string() => Value::Float(10.0),
+            },
+        );
+        graph.graph.advance_generation();
+        // Without a label, the values are checked in both directions
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![vec![(0, stem), (1, steep)], vec![(0, steep), (1, stem)]]
+        );
+
+        // Edges without the value don't match
+        let rule = rule_from_yaml(
+            "from: {nodes: [{id: 0}, {id: 1}], edges: [{from: 0, to: 1, values: {stiffness: [exists]}}]}\nto: []",
+        );
+        assert!(sorted_matches(&rule, &graph).is_empty());
+    }
+
+    #[test]
+    #[timeout(500)]
+    fn test_match_skips_dirty() {
+        let rule = get_test_rule();
+        let mut graph = get_test_graph();
+        // New nodes and edges can't be matched until the next generation
+        let new = graph.insert_node();
+        graph.graph.add_edge(1, new).unwrap();
+        assert_eq!(
+            sorted_matches(&rule, &graph),
+            vec![vec![(0, 0), (1, 1)], vec![(0, 1), (1, 0)]]
+        );
+        // Neither can nodes that were changed
+        graph.graph.set_node_dirty(0);
+        assert!(sorted_matches(&rule, &graph).is_empty());
+
+        graph.graph.advance_generation();
+        assert_eq!(sorted_matches(&rule, &graph).len(), 4);
     }
 }
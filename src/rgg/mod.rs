@@ -2,17 +2,34 @@
 // Not a full-fledged RGG (yet?) because it's devilishly difficult, but it still acts on graphs
 
 pub mod condition;
-mod dirty_graph;
+pub mod derivation;
+pub mod dirty_graph;
+pub mod edge;
+pub mod environment;
+pub mod error;
+pub mod expr;
+pub(crate) mod fingerprint;
 pub mod matcher;
 pub mod node;
-mod procedures;
+pub mod phase;
+pub mod procedures;
 pub mod rgg_graph;
 pub mod rule;
 mod serde;
+pub mod validate;
 pub mod value;
 
-pub use condition::Condition;
-pub use node::{FromNode, Node, ToNode};
+pub use condition::{Condition, Operand};
+pub use derivation::{ConflictPolicy, DerivationMode};
+pub use dirty_graph::DirtyGraph;
+pub use edge::{EdgeLabel, Neighbor, PatternEdge};
+pub use environment::Environment;
+pub use error::RggError;
+pub use matcher::MatchingState;
+pub use node::{FromNode, Node, ToNode, ValueExpr};
+pub use phase::{Phase, RuleGroup};
+pub use procedures::{ApplyResult, Procedure};
 pub use rgg_graph::RggGraph;
-pub use rule::{NodeSet, Rule};
+pub use rule::{AppliedMatch, NodeSet, Rule, RuleFailure, RuleMatch, RuleResult, RuleStats};
+pub use validate::RuleProblem;
 pub use value::Value;
@@ -1,18 +1,22 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use super::Value;
+use crate::rgg::condition::StringPattern;
+use crate::rgg::expr::{self, Variables};
 use crate::rgg::value::RGGType;
 use crate::rgg::Condition;
 use meval::Context;
-use rand::Rng;
-use serde::Deserialize;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 /// Represents the values stored in a node in an RGG.
 pub struct Node {
     pub name: String,
     #[serde(default)]
-    pub values: HashMap<String, Value>,
+    pub values: BTreeMap<String, Value>,
 }
 
 impl Node {
@@ -31,38 +35,38 @@ impl Default for Node {
 }
 
 /// Identify a node to match against
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct FromNode {
     /// Identify the node in the context of a rule
     pub id: i32,
-    /// Identify the "name" of the node. Optional.
+    /// Identify the "name" of the node, either exactly or by a glob or regex. Optional.
     #[serde(default)]
-    pub name: Option<String>,
+    pub name: Option<StringPattern>,
     #[serde(default)]
     /// Specify any potential values the node has.
-    pub values: HashMap<String, Condition>,
+    pub values: BTreeMap<String, Condition>,
 }
 
 impl FromNode {
+    /// Check whether the name of the provided node matches, if a name is specified.
+    pub fn match_name(&self, node: &Node) -> bool {
+        match self.name.as_ref() {
+            Some(name) => name.is_match(&node.name),
+            None => true,
+        }
+    }
+
     /// Check whether the node can match the provided node.
-    pub fn match_node(&self, node: &Node) -> bool {
+    /// Conditions may refer to `parameters`.
+    pub fn match_node(&self, node: &Node, parameters: &Variables) -> bool {
         // If name is specified, needs to match.
-        if let Some(name) = self.name.as_ref() {
-            if *name != node.name {
-                return false;
-            }
+        if !self.match_name(node) {
+            return false;
         }
 
         // If any values are specified, need to match conditions.
         for (name, condition) in &self.values {
-            let result = match node.values.get(name) {
-                None => false,
-                Some(thing) => match thing.rgg_type {
-                    RGGType::Int => condition.check(thing.get::<i32>()),
-                    RGGType::Float => condition.check(thing.get::<f32>()),
-                },
-            };
-            if !result {
+            if !condition.check(node.values.get(name), parameters) {
                 return false;
             }
         }
@@ -71,41 +75,181 @@ impl FromNode {
     }
 }
 
+/// Describes how to compute a single value of a ToNode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueExpr {
+    /// A fixed value, used as-is.
+    Literal(Value),
+    /// An expression evaluated against the base node's values.
+    /// If no type is declared, the type of the base node's value with the same name is kept,
+    /// and the result is a float if there is no such value.
+    Expr {
+        expr: String,
+        rgg_type: Option<RGGType>,
+    },
+}
+
+impl From<&str> for ValueExpr {
+    fn from(expr: &str) -> Self {
+        ValueExpr::Expr {
+            expr: expr.to_string(),
+            rgg_type: None,
+        }
+    }
+}
+
+/// Returned when a ToNode value could not be computed.
+#[derive(Debug)]
+pub enum ExprError {
+    /// The expression could not be parsed or referred to something that does not exist.
+    Eval {
+        name: String,
+        expr: String,
+        error: meval::Error,
+    },
+    /// The expression evaluated to something that does not fit the value's type.
+    WrongType {
+        name: String,
+        expr: String,
+        expected: RGGType,
+        result: f64,
+    },
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExprError::Eval { name, expr, error } => write!(
+                f,
+                "Could not evaluate {:?} for value {:?}: {}",
+                expr, name, error
+            ),
+            ExprError::WrongType {
+                name,
+                expr,
+                expected,
+                result,
+            } => write!(
+                f,
+                "Expression {:?} for value {:?} produced {}, which is not a valid {:?}",
+                expr, name, result, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+impl ValueExpr {
+    /// Compute the value named `name`.
+    fn eval(
+        &self,
+        name: &str,
+        context: &Context,
+        base: Option<&BTreeMap<String, Value>>,
+    ) -> Result<Value, ExprError> {
+        let (expr, rgg_type) = match self {
+            ValueExpr::Literal(value) => return Ok(value.clone()),
+            ValueExpr::Expr { expr, rgg_type } => (expr, rgg_type),
+        };
+        let rgg_type = rgg_type.unwrap_or_else(|| match base.and_then(|values| values.get(name)) {
+            Some(Value::Int(_)) => RGGType::Int,
+            Some(Value::Bool(_)) => RGGType::Bool,
+            _ => RGGType::Float,
+        });
+        // Booleans may be conditions like `len > 2`
+        let result = match rgg_type {
+            RGGType::Bool => expr::eval_bool(expr, context).map(|b| if b { 1.0 } else { 0.0 }),
+            _ => expr::eval_number(expr, context),
+        }
+        .map_err(|error| ExprError::Eval {
+            name: name.to_string(),
+            expr: expr.clone(),
+            error,
+        })?;
+        let wrong_type = || ExprError::WrongType {
+            name: name.to_string(),
+            expr: expr.clone(),
+            expected: rgg_type,
+            result,
+        };
+        match rgg_type {
+            RGGType::Int => {
+                if result.fract() != 0.0 || result < i32::MIN as f64 || result > i32::MAX as f64 {
+                    return Err(wrong_type());
+                }
+                Ok(Value::Int(result as i32))
+            }
+            RGGType::Bool => {
+                if result == 0.0 {
+                    Ok(Value::Bool(false))
+                } else if result == 1.0 {
+                    Ok(Value::Bool(true))
+                } else {
+                    Err(wrong_type())
+                }
+            }
+            RGGType::Float if result.is_finite() => Ok(Value::Float(result as f32)),
+            _ => Err(wrong_type()),
+        }
+    }
+}
+
 /// Define a replacement node.
 /// For replace, can use operations relative to the previous node's values.
-/// For all nodes, can use some operations for values, such as rand
+/// For all nodes, can use some operations for values, such as rand, and the values of any matched node
 #[derive(Deserialize, Debug, Clone)]
 pub struct ToNode {
     pub name: String,
-    pub values: HashMap<String, String>,
+    pub values: BTreeMap<String, ValueExpr>,
 }
 
 impl ToNode {
-    /// Evaluate the values of the tonode to create a normal node
-    pub fn eval(&self, base_node: Option<&Node>) -> Node {
-        let mut context = Context::new();
-        if let Some(base_node) = base_node {
-            for (name, value) in &base_node.values {
-                context.var(name, value.get::<f32>() as f64);
-            }
-        }
-        context.func2("rand", |min, max| {
-            let mut rng = rand::thread_rng();
-            rng.gen_range(min..max)
-        });
-        let mut values = HashMap::new();
-        for (name, expr) in &self.values {
-            let val = meval::eval_str_with_context(expr, &context).unwrap();
-            values.insert(name.to_string(), Value::new_float(val as f32));
-        }
-
-        Node {
+    /// Evaluate the values of the tonode to create a normal node.
+    /// The base node's values are available by name, on top of the given variables.
+    /// `rand(min, max)` draws from the provided rng, so results are reproducible for a seeded rng.
+    pub fn eval(
+        &self,
+        base_node: Option<&Node>,
+        variables: &Variables,
+        rng: &mut dyn RngCore,
+    ) -> Result<Node, ExprError> {
+        Ok(Node {
             name: self.name.clone(),
-            values,
-        }
+            values: eval_values(
+                &self.values,
+                base_node.map(|node| &node.values),
+                variables,
+                rng,
+            )?,
+        })
     }
 }
 
+/// Evaluate named expressions, such as the values of a ToNode or of an edge.
+/// The base values are available by name, on top of the given variables, and decide the types of
+/// the results where the expressions don't.
+pub fn eval_values(
+    exprs: &BTreeMap<String, ValueExpr>,
+    base: Option<&BTreeMap<String, Value>>,
+    variables: &Variables,
+    rng: &mut dyn RngCore,
+) -> Result<BTreeMap<String, Value>, ExprError> {
+    let rng = RefCell::new(rng);
+    let mut variables = variables.clone();
+    if let Some(base) = base {
+        expr::add_values(&mut variables, None, base);
+    }
+    let mut context = expr::new_context(&variables);
+    context.func2("rand", |min, max| rng.borrow_mut().gen_range(min..max));
+    let mut values = BTreeMap::new();
+    for (name, expr) in exprs {
+        let val = expr.eval(name, &context, base)?;
+        values.insert(name.to_string(), val);
+    }
+    Ok(values)
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -123,27 +267,138 @@ age: 30.0
         };
         let tonode = ToNode {
             name: "bye".to_string(),
-            values: maplit::hashmap! {
-                "age".to_string() => "age - 1".to_string()
+            values: maplit::btreemap! {
+                "age".to_string() => "age - 1".into()
             },
         };
-        let result = tonode.eval(Some(&context));
+        let result = tonode
+            .eval(Some(&context), &Variables::new(), &mut rand::thread_rng())
+            .unwrap();
         assert_eq!(result.name, "bye");
-        assert_eq!(result.values["age"].get::<f32>(), 29.0);
+        assert_eq!(result.values["age"].as_float(), Ok(29.0));
+    }
+
+    #[test]
+    fn test_tonode_keeps_types() {
+        let context = Node {
+            name: "Hi".to_string(),
+            values: serde_yaml::from_str("{age: 30, alive: true}").unwrap(),
+        };
+        let tonode = ToNode {
+            name: "bye".to_string(),
+            values: maplit::btreemap! {
+                "age".to_string() => "age + 1".into(),
+                "alive".to_string() => "0".into(),
+                "young".to_string() => ValueExpr::Expr {
+                    expr: "age < 40 && alive".to_string(),
+                    rgg_type: Some(RGGType::Bool),
+                },
+                "half".to_string() => "age / 2".into(),
+                "count".to_string() => ValueExpr::Expr {
+                    expr: "age - 10".to_string(),
+                    rgg_type: Some(RGGType::Int),
+                },
+                "name".to_string() => ValueExpr::Literal(Value::from("leaf")),
+            },
+        };
+        let result = tonode
+            .eval(Some(&context), &Variables::new(), &mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(result.values["age"], Value::Int(31));
+        assert_eq!(result.values["alive"], Value::Bool(false));
+        assert_eq!(result.values["young"], Value::Bool(true));
+        assert_eq!(result.values["half"], Value::Float(15.0));
+        assert_eq!(result.values["count"], Value::Int(20));
+        assert_eq!(result.values["name"], Value::from("leaf"));
+    }
+
+    #[test]
+    fn test_tonode_wrong_type() {
+        let context = Node {
+            name: "Hi".to_string(),
+            values: serde_yaml::from_str("{age: 30}").unwrap(),
+        };
+        let tonode = ToNode {
+            name: "bye".to_string(),
+            values: maplit::btreemap! {
+                "age".to_string() => "age / 4".into()
+            },
+        };
+        match tonode.eval(Some(&context), &Variables::new(), &mut rand::thread_rng()) {
+            Err(ExprError::WrongType { expected, .. }) => assert_eq!(expected, RGGType::Int),
+            other => panic!("Expected a type error, got {:?}", other),
+        }
+
+        let tonode = ToNode {
+            name: "bye".to_string(),
+            values: maplit::btreemap! {
+                "age".to_string() => "unknown + 1".into()
+            },
+        };
+        assert!(matches!(
+            tonode.eval(Some(&context), &Variables::new(), &mut rand::thread_rng()),
+            Err(ExprError::Eval { .. })
+        ));
     }
 
     #[test]
     fn test_tonode_method() {
         let tonode = ToNode {
             name: "bye".to_string(),
-            values: maplit::hashmap! {
-                "len".to_string() => "rand(1, 5)".to_string()
+            values: maplit::btreemap! {
+                "len".to_string() => "rand(1, 5)".into()
             },
         };
-        let result = tonode.eval(None);
+        let result = tonode
+            .eval(None, &Variables::new(), &mut rand::thread_rng())
+            .unwrap();
         assert_eq!(result.name, "bye");
-        let len = result.values["len"].get::<f32>();
+        let len = result.values["len"].as_float().unwrap();
         assert!(len > 0.0);
         assert!(len < 5.0);
     }
+
+    #[test]
+    fn test_tonode_seeded() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let tonode = ToNode {
+            name: "bye".to_string(),
+            values: maplit::btreemap! {
+                "len".to_string() => "rand(1, 5)".into()
+            },
+        };
+        let first = tonode
+            .eval(None, &Variables::new(), &mut StdRng::seed_from_u64(42))
+            .unwrap();
+        let second = tonode
+            .eval(None, &Variables::new(), &mut StdRng::seed_from_u64(42))
+            .unwrap();
+        assert_eq!(first.values["len"], second.values["len"]);
+    }
+
+    #[test]
+    fn test_tonode_variables() {
+        let context = Node {
+            name: "Hi".to_string(),
+            values: serde_yaml::from_str("{len: 2.0, depth: 7}").unwrap(),
+        };
+        let variables = maplit::btreemap! {
+            "n1__len".to_string() => 4.0,
+            "depth".to_string() => 3.0,
+        };
+        let tonode = ToNode {
+            name: "bye".to_string(),
+            values: maplit::btreemap! {
+                "len".to_string() => "(len + n1.len) / 2".into(),
+                "depth".to_string() => "depth".into(),
+            },
+        };
+        let result = tonode
+            .eval(Some(&context), &variables, &mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(result.values["len"], Value::Float(3.0));
+        // The base node's own values take precedence
+        assert_eq!(result.values["depth"], Value::Int(7));
+    }
 }
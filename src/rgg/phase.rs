@@ -0,0 +1,220 @@
+// Rule groups and phases, which control the order in which rules are applied in each step.
+use crate::rgg::derivation::{parallel_step, sequential_step};
+use crate::rgg::{DerivationMode, Environment, RggError, RggGraph, Rule, RuleResult};
+use rand::RngCore;
+use serde::Deserialize;
+
+/// How the rules of a group interact.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupMode {
+    /// Every rule is applied to all of its matches.
+    All,
+    /// Only the first rule that matches a node is applied to it.
+    Ordered,
+}
+
+impl Default for GroupMode {
+    fn default() -> Self {
+        GroupMode::All
+    }
+}
+
+/// How often a group is applied in each step.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeat {
+    Once,
+    /// Until the rules no longer change the graph.
+    Fixpoint,
+}
+
+impl Default for Repeat {
+    fn default() -> Self {
+        Repeat::Once
+    }
+}
+
+fn default_max_iterations() -> usize {
+    100
+}
+
+/// A list of rules that are applied together.
+#[derive(Deserialize, Clone)]
+pub struct RuleGroup {
+    #[serde(default)]
+    pub mode: GroupMode,
+    #[serde(default)]
+    pub repeat: Repeat,
+    /// Stop repeating after this many iterations, even if the graph still changes.
+    #[serde(default = "default_max_iterations")]
+    pub max_iterations: usize,
+    pub rules: Vec<Rule>,
+}
+
+/// A named list of rule groups, applied one after the other.
+#[derive(Deserialize, Clone)]
+pub struct Phase {
+    pub name: String,
+    pub groups: Vec<RuleGroup>,
+}
+
+impl RuleGroup {
+    /// A group that applies all of the rules once.
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            mode: GroupMode::All,
+            repeat: Repeat::Once,
+            max_iterations: default_max_iterations(),
+            rules,
+        }
+    }
+
+    /// Apply the group's rules. `rngs` holds the random stream of every rule.
+    /// When repeating, the generation is advanced between iterations so that changed nodes
+    /// can be matched again.
+    pub fn apply<R: RngCore>(
+        &self,
+        graph: &mut RggGraph,
+        rngs: &mut [R],
+        derivation: DerivationMode,
+        conflict_rng: &mut dyn RngCore,
+        env: &Environment,
+    ) -> Result<RuleResult, RggError> {
+        let exclusive = self.mode == GroupMode::Ordered;
+        let mut result = RuleResult::new();
+        let mut iteration = 0;
+        loop {
+            if iteration > 0 {
+                graph.graph.advance_generation();
+            }
+            let step = match derivation {
+                DerivationMode::Sequential => {
+                    sequential_step(&self.rules, graph, rngs, exclusive, env)
+                }
+                DerivationMode::Parallel(policy) => parallel_step(
+                    &self.rules,
+                    graph,
+                    rngs,
+                    policy,
+                    exclusive,
+                    conflict_rng,
+                    env,
+                ),
+            }?;
+            let unchanged = step.is_empty();
+            result.add(step);
+            iteration += 1;
+
+            if self.repeat == Repeat::Once || unchanged {
+                break;
+            }
+            if iteration >= self.max_iterations {
+                log::warn!(
+                    "Rule group did not reach a fixpoint within {} iterations",
+                    self.max_iterations
+                );
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgg::{Node, Value};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn apply(group: &RuleGroup, graph: &mut RggGraph) -> RuleResult {
+        graph.graph.advance_generation();
+        let mut rngs = vec![StdRng::seed_from_u64(0); group.rules.len()];
+        group
+            .apply(
+                graph,
+                &mut rngs,
+                DerivationMode::Sequential,
+                &mut StdRng::seed_from_u64(0),
+                &Environment::default(),
+            )
+            .unwrap()
+    }
+
+    fn single_stem() -> RggGraph {
+        let mut graph = RggGraph::new();
+        let mut stem = Node::new("stem");
+        stem.values.insert("len".to_string(), Value::Int(0));
+        graph.insert_node_with(stem);
+        graph
+    }
+
+    const COMPETING: &str = r#"
+rules:
+  - from:
+      nodes: [{id: 0, name: stem}]
+    to:
+      - add: {neighbors: [0], node: {name: leaf, values: {}}}
+  - from:
+      nodes: [{id: 0, name: stem}]
+    to:
+      - add: {neighbors: [0], node: {name: flower, values: {}}}
+"#;
+
+    #[test]
+    fn test_all() {
+        let group: RuleGroup = serde_yaml::from_str(COMPETING).unwrap();
+        let mut graph = single_stem();
+        apply(&group, &mut graph);
+        assert_eq!(graph.order(), 3);
+    }
+
+    #[test]
+    fn test_ordered() {
+        let group: RuleGroup =
+            serde_yaml::from_str(&format!("mode: ordered{}", COMPETING)).unwrap();
+        let mut graph = single_stem();
+        apply(&group, &mut graph);
+        assert_eq!(graph.order(), 2);
+        assert_eq!(graph.values[&1].name, "leaf");
+    }
+
+    #[test]
+    fn test_fixpoint() {
+        let group: RuleGroup = serde_yaml::from_str(
+            r#"
+repeat: fixpoint
+rules:
+  - from:
+      nodes: [{id: 0, name: stem, values: {len: [lt, 5]}}]
+    to:
+      - replace: {target: 0, with: {name: stem, values: {len: len + 1}}}
+"#,
+        )
+        .unwrap();
+        let mut graph = single_stem();
+        let result = apply(&group, &mut graph);
+        assert_eq!(graph.values[&0].values["len"], Value::Int(5));
+        assert_eq!(result.modified.len(), 5);
+    }
+
+    #[test]
+    fn test_max_iterations() {
+        let group: RuleGroup = serde_yaml::from_str(
+            r#"
+repeat: fixpoint
+max_iterations: 3
+rules:
+  - from:
+      nodes: [{id: 0, name: stem}]
+    to:
+      - replace: {target: 0, with: {name: stem, values: {len: len + 1}}}
+"#,
+        )
+        .unwrap();
+        let mut graph = single_stem();
+        apply(&group, &mut graph);
+        assert_eq!(graph.values[&0].values["len"], Value::Int(3));
+    }
+}
@@ -1,43 +1,53 @@
+use crate::rgg::expr::{self, Variables};
+use crate::rgg::node::{self, ValueExpr};
 use crate::rgg::rgg_graph::RggGraph;
-use crate::rgg::ToNode;
+use crate::rgg::{EdgeLabel, Environment, Neighbor, RggError, ToNode, Value};
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 
-use gamma::graph::{AppendableGraph, Graph};
+use gamma::graph::{Graph, RemovableGraph};
+use rand::RngCore;
 use serde::Deserialize;
 
 /// Rules to follow to go from LHS to RHS
 /// CBA to figure out double pushout so i will instead "cheat" by having a procedure to follow
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum Procedure {
     Delete(DeleteProcedure),
     Replace(ReplaceProcedure),
     Add(AddProcedure),
     Merge(MergeProcedure),
+    #[serde(rename = "set_edge")]
+    SetEdge(SetEdgeProcedure),
+    Connect(ConnectProcedure),
+    Disconnect(DisconnectProcedure),
+    Split(SplitProcedure),
+    #[serde(rename = "move_edge")]
+    MoveEdge(MoveEdgeProcedure),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DeleteProcedure {
     pub target: i32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct ReplaceProcedure {
     pub target: i32,
     #[serde(rename = "with")]
     pub replacement: ToNode,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct AddProcedure {
-    /// All the nodes that this new node should have an edge to
-    pub neighbors: Vec<i32>,
+    /// All the nodes that this new node should have an edge to, and the labels of those edges
+    pub neighbors: Vec<Neighbor>,
     #[serde(rename = "node")]
     pub new_node: ToNode,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MergeProcedure {
     /// All the nodes to merge
     pub targets: Vec<i32>,
@@ -45,10 +55,146 @@ pub struct MergeProcedure {
     pub final_node: i32,
 }
 
-enum CheckDirty {
-    Clean(usize),
-    DoesNotExist,
-    Dirty,
+/// Change the label or values of an existing edge between two matched nodes.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SetEdgeProcedure {
+    pub from: i32,
+    pub to: i32,
+    /// The new label. A directed label points from `from` to `to`. The label is kept if not given.
+    #[serde(default)]
+    pub label: Option<EdgeLabel>,
+    /// Evaluated against the current values of the edge. Values that are not given are kept.
+    #[serde(default)]
+    pub values: BTreeMap<String, ValueExpr>,
+}
+
+/// Add an edge between two matched nodes, written as `[from, to]`, `[from, to, label]` or as a map
+/// that may also have values for the edge. An existing edge is relabeled and given the values.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectProcedure {
+    pub from: i32,
+    pub to: i32,
+    /// A directed label points from `from` to `to`.
+    #[serde(default)]
+    pub label: EdgeLabel,
+    /// Evaluated like the values of a new node, without a base node.
+    #[serde(default)]
+    pub values: BTreeMap<String, ValueExpr>,
+}
+
+/// Remove the edge between two matched nodes, written as `[from, to]`.
+/// If one of them is the ancestor of the other, the other loses its ancestor.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DisconnectProcedure {
+    pub from: i32,
+    pub to: i32,
+}
+
+/// Insert a new node on the edge between two matched nodes, e.g. a new internode.
+/// The half of the edge at its source, or at `from` for undirected edges, keeps the label and
+/// values of the edge. If one end was the ancestor of the other, the new node goes in between,
+/// otherwise it descends from `from`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SplitProcedure {
+    pub from: i32,
+    pub to: i32,
+    #[serde(rename = "node")]
+    pub new_node: ToNode,
+    /// The label of the other half of the edge. The edge's label is used if not given.
+    #[serde(default)]
+    pub label: Option<EdgeLabel>,
+}
+
+/// Move an edge between two matched nodes to two other matched nodes, keeping its label, direction
+/// and values, e.g. `{edge: [0, 1], to: [2, 1]}` to reattach the branch 1 from 0 to 2.
+/// A node that hung off the other end by the edge keeps hanging off it if it stays an end.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MoveEdgeProcedure {
+    pub edge: (i32, i32),
+    pub to: (i32, i32),
+}
+
+/// The host node of a rule node, if it still exists.
+fn mapped_node(graph: &RggGraph, mapping: &HashMap<i32, usize>, rule_id: i32) -> Option<usize> {
+    mapping
+        .get(&rule_id)
+        .copied()
+        .filter(|id| graph.graph.has_node(*id))
+}
+
+/// The host nodes of two rule nodes, if there still is an edge between them.
+fn mapped_edge(
+    graph: &RggGraph,
+    mapping: &HashMap<i32, usize>,
+    from: i32,
+    to: i32,
+) -> Option<(usize, usize)> {
+    let from = mapped_node(graph, mapping, from)?;
+    let to = mapped_node(graph, mapping, to)?;
+    Some((from, to)).filter(|(from, to)| graph.graph.has_edge(*from, *to).unwrap_or(false))
+}
+
+/// The ancestor and the descendant, if one end of an edge is the direct ancestor of the other.
+fn ancestry(graph: &RggGraph, a: usize, b: usize) -> Option<(usize, usize)> {
+    if graph.graph.get_ancestor(b) == Some(a) {
+        Some((a, b))
+    } else if graph.graph.get_ancestor(a) == Some(b) {
+        Some((b, a))
+    } else {
+        None
+    }
+}
+
+/// Make `ancestor` the ancestor of `me`, unless that would make the ancestors form a cycle.
+fn set_ancestor(graph: &mut RggGraph, me: usize, ancestor: usize) {
+    let mut current = Some(ancestor);
+    let mut steps = 0;
+    while let Some(id) = current {
+        if id == me {
+            log::warn!(
+                "Not making {} the ancestor of its ancestor {}",
+                me,
+                ancestor
+            );
+            graph.graph.remove_ancestor(me);
+            return;
+        }
+        steps += 1;
+        if steps > graph.order() {
+            break;
+        }
+        current = graph.graph.get_ancestor(id);
+    }
+    graph.graph.add_ancestor(me, ancestor);
+}
+
+/// The variables available to a ToNode's expressions: the values of all matched nodes,
+/// the environment, and the degree and depth of the node that the new node is based on.
+fn variables(
+    graph: &RggGraph,
+    mapping: &HashMap<i32, usize>,
+    base: Option<usize>,
+    env: &Environment,
+) -> Variables {
+    let mut variables = Variables::new();
+    for (rule_id, id) in mapping {
+        if let Some(node) = graph.values.get(id) {
+            expr::add_values(&mut variables, Some(*rule_id), &node.values);
+        }
+    }
+    env.add_variables(&mut variables);
+    if let Some(base) = base {
+        if let Ok(degree) = graph.graph.degree(base) {
+            variables.insert("degree".to_string(), degree as f64);
+        }
+        variables.insert("depth".to_string(), graph.depth(base) as f64);
+    }
+    variables
 }
 
 /// Tracks the results of a procedure application. Useful for rendering.
@@ -56,143 +202,341 @@ pub enum ApplyResult {
     Removed(Vec<usize>),
     Added(usize),
     Modified(usize),
-    None,
-    Failed,
+    /// The label or values of the edge between the two nodes changed.
+    ModifiedEdge(usize, usize),
+    AddedEdge(usize, usize),
+    RemovedEdge(usize, usize),
+    /// A new node was inserted on the edge between `from` and `to`.
+    Split {
+        node: usize,
+        from: usize,
+        to: usize,
+    },
+    MovedEdge {
+        from: (usize, usize),
+        to: (usize, usize),
+    },
 }
 
 impl Procedure {
     /// Check whether all targets specified exist in the mapping.
     pub fn targets_exist(&self, mapping: &HashMap<i32, usize>) -> bool {
+        self.targets().iter().all(|id| mapping.contains_key(id))
+    }
+
+    /// The rule ids of all nodes that the procedure refers to.
+    pub fn targets(&self) -> Vec<i32> {
         match self {
-            Procedure::Delete(proc) => mapping.contains_key(&proc.target),
-            Procedure::Replace(proc) => mapping.contains_key(&proc.target),
-            Procedure::Add(proc) => {
-                for neighbor in &proc.neighbors {
-                    if !mapping.contains_key(neighbor) {
-                        return false;
-                    }
-                }
-                true
-            }
-            Procedure::Merge(proc) => {
-                for target in &proc.targets {
-                    if !mapping.contains_key(target) {
-                        return false;
-                    }
-                }
-                true
-            }
+            Procedure::Delete(proc) => vec![proc.target],
+            Procedure::Replace(proc) => vec![proc.target],
+            Procedure::Add(proc) => proc.neighbors.iter().map(|neighbor| neighbor.id).collect(),
+            Procedure::Merge(proc) => proc.targets.clone(),
+            Procedure::SetEdge(proc) => vec![proc.from, proc.to],
+            Procedure::Connect(proc) => vec![proc.from, proc.to],
+            Procedure::Disconnect(proc) => vec![proc.from, proc.to],
+            Procedure::Split(proc) => vec![proc.from, proc.to],
+            Procedure::MoveEdge(proc) => vec![proc.edge.0, proc.edge.1, proc.to.0, proc.to.1],
         }
     }
 
-    /// Apply the contents of the Procedure to a mapped graph.
-    /// Returns false on failure to execute.
-    pub fn apply(&self, graph: &mut RggGraph, mapping: &mut HashMap<i32, usize>) -> ApplyResult {
+    /// The name the procedure is written as in rule files.
+    pub fn name(&self) -> &'static str {
         match self {
-            Procedure::Delete(proc) => match mapping.get(&proc.target).copied() {
-                Some(target) => {
-                    graph.remove_node(target);
-                    mapping.remove(&proc.target);
-                    ApplyResult::Removed(vec![target])
-                }
-                None => {
-                    log::error!("Could not delete node {}", proc.target);
-                    ApplyResult::Failed
-                }
-            },
-            Procedure::Replace(proc) => match mapping.get(&proc.target) {
-                Some(target) => {
-                    let new_node = proc.replacement.eval(graph.values.get(target));
-                    graph.values.insert(*target, new_node);
-                    ApplyResult::Modified(*target)
-                }
-                None => {
-                    log::error!("Could not replace node {}", proc.target);
-                    ApplyResult::Failed
-                }
-            },
+            Procedure::Delete(_) => "delete",
+            Procedure::Replace(_) => "replace",
+            Procedure::Add(_) => "add",
+            Procedure::Merge(_) => "merge",
+            Procedure::SetEdge(_) => "set_edge",
+            Procedure::Connect(_) => "connect",
+            Procedure::Disconnect(_) => "disconnect",
+            Procedure::Split(_) => "split",
+            Procedure::MoveEdge(_) => "move_edge",
+        }
+    }
+
+    /// The rule ids of the existing nodes that the procedure deletes or changes.
+    /// Nodes that only get new edges are not included.
+    pub fn rewritten_targets(&self) -> Vec<i32> {
+        match self {
+            Procedure::Delete(proc) => vec![proc.target],
+            Procedure::Replace(proc) => vec![proc.target],
+            Procedure::Add(_) => vec![],
+            Procedure::Merge(proc) => proc.targets.clone(),
+            Procedure::SetEdge(_)
+            | Procedure::Connect(_)
+            | Procedure::Disconnect(_)
+            | Procedure::Split(_)
+            | Procedure::MoveEdge(_) => vec![],
+        }
+    }
+
+    /// The rule ids of the ends of the existing edges that the procedure removes or changes.
+    pub fn rewritten_edges(&self) -> Vec<(i32, i32)> {
+        match self {
+            Procedure::SetEdge(proc) => vec![(proc.from, proc.to)],
+            Procedure::Connect(proc) => vec![(proc.from, proc.to)],
+            Procedure::Disconnect(proc) => vec![(proc.from, proc.to)],
+            Procedure::Split(proc) => vec![(proc.from, proc.to)],
+            Procedure::MoveEdge(proc) => vec![proc.edge],
+            Procedure::Delete(_)
+            | Procedure::Replace(_)
+            | Procedure::Add(_)
+            | Procedure::Merge(_) => vec![],
+        }
+    }
+
+    /// Apply the contents of the Procedure to a mapped graph.
+    /// Everything that is changed is marked dirty. Whether the targets are still clean is up to
+    /// the caller, so that a match can rewrite what its own earlier procedures changed.
+    /// New values are drawn from `rng` wherever the rule asks for randomness.
+    /// Fails if a target is missing or a value can't be computed, which may leave the graph
+    /// partially changed.
+    pub fn apply(
+        &self,
+        graph: &mut RggGraph,
+        mapping: &mut HashMap<i32, usize>,
+        rng: &mut dyn RngCore,
+        env: &Environment,
+    ) -> Result<ApplyResult, RggError> {
+        let result = match self {
+            Procedure::Delete(proc) => {
+                let target = mapped_node(graph, mapping, proc.target)
+                    .ok_or(RggError::MissingNode(proc.target))?;
+                // What hung off the node hangs off its ancestor from now on
+                graph.graph.remove_children(target, None);
+                graph.remove_node(target);
+                mapping.remove(&proc.target);
+                ApplyResult::Removed(vec![target])
+            }
+            Procedure::Replace(proc) => {
+                let target = mapped_node(graph, mapping, proc.target)
+                    .ok_or(RggError::MissingNode(proc.target))?;
+                let variables = variables(graph, mapping, Some(target), env);
+                let new_node = proc
+                    .replacement
+                    .eval(graph.values.get(&target), &variables, rng)?;
+                graph.set_node(target, new_node);
+                graph.graph.set_node_dirty(target);
+                ApplyResult::Modified(target)
+            }
             Procedure::Add(proc) => {
-                let node_id = graph.insert_node();
-                let mut ancestored = None;
+                // Look up all neighbors before touching the graph
+                let mut neighbors = Vec::with_capacity(proc.neighbors.len());
                 for neighbor in &proc.neighbors {
-                    match mapping.get(&neighbor) {
-                        Some(neighbor) => {
-                            if ancestored.is_none() {
-                                graph.graph.add_ancestor(node_id, *neighbor);
-                                log::debug!("Ancestor of {} is {}", node_id, neighbor);
-                                ancestored = Some(*neighbor);
-                            }
-                            graph.graph.add_edge(node_id, *neighbor).unwrap();
-                        }
-                        None => {
-                            log::warn!(
-                                "Could not find specified neighbor {} in mapping {:?}",
-                                neighbor,
-                                mapping
-                            );
-                            return ApplyResult::Failed;
-                        }
+                    match mapped_node(graph, mapping, neighbor.id) {
+                        Some(id) => neighbors.push((id, neighbor)),
+                        None => return Err(RggError::MissingNode(neighbor.id)),
                     }
                 }
-                let node = match ancestored {
-                    Some(neighbor) => {
-                        let context = graph.values.get(&neighbor);
-                        proc.new_node.eval(context)
-                    }
-                    None => proc.new_node.eval(None),
-                };
-                graph.values.insert(node_id, node);
+                // The first neighbor is the ancestor and provides the context for values
+                let ancestor = neighbors.first().map(|(id, _)| *id);
+                let variables = variables(graph, mapping, ancestor, env);
+                let node = proc.new_node.eval(
+                    ancestor.and_then(|a| graph.values.get(&a)),
+                    &variables,
+                    rng,
+                )?;
+                let mut edges = Vec::with_capacity(neighbors.len());
+                for (id, neighbor) in neighbors {
+                    let values = node::eval_values(&neighbor.values, None, &variables, rng)?;
+                    edges.push((id, neighbor.label, values));
+                }
+                let node_id = graph.insert_node_with(node);
+                if let Some(ancestor) = ancestor {
+                    graph.graph.add_ancestor(node_id, ancestor);
+                    log::debug!("Ancestor of {} is {}", node_id, ancestor);
+                }
+                for (neighbor, label, values) in edges {
+                    graph.graph.add_labeled_edge(neighbor, node_id, label)?;
+                    graph.graph.set_edge_values(neighbor, node_id, values);
+                }
                 ApplyResult::Added(node_id)
             }
             Procedure::Merge(proc) => {
-                // Make a list of all edges that connect to all neighbors
-                let mut neighbors: HashSet<usize> = HashSet::new();
+                // Make a list of all edges that connect to all neighbors, with their label,
+                // whether they start at the neighbor and their values
+                let mut neighbors: BTreeMap<usize, (EdgeLabel, bool, BTreeMap<String, Value>)> =
+                    BTreeMap::new();
                 let mut ancestor = None;
                 // Ensure that all nodes to be merged exist
                 for rule_id in &proc.targets {
-                    match mapping.get(rule_id) {
-                        Some(id) => {
-                            if ancestor == None && graph.graph.get_ancestor(*id).is_some() {
-                                ancestor = graph.graph.get_ancestor(*id);
-                            }
-                            graph
-                                .graph
-                                .neighbors(*id)
-                                .expect("Could not unwrap neighbors()")
-                                .for_each(|n| {
-                                    neighbors.insert(*n);
-                                });
-                        }
-                        None => {
-                            log::error!(
-                                "Could not merge because missing mapping for node {}",
-                                rule_id
-                            );
-                            return ApplyResult::Failed;
-                        }
+                    let id = mapped_node(graph, mapping, *rule_id)
+                        .ok_or(RggError::MissingNode(*rule_id))?;
+                    if ancestor == None && graph.graph.get_ancestor(id).is_some() {
+                        ancestor = graph.graph.get_ancestor(id);
+                    }
+                    for n in graph.graph.neighbors(id)? {
+                        let label = graph.graph.edge_label(id, *n).unwrap_or_default();
+                        let from_neighbor = graph.graph.edge_source(id, *n) == Some(*n);
+                        let values = graph.graph.edge_values(id, *n).cloned().unwrap_or_default();
+                        neighbors
+                            .entry(*n)
+                            .or_insert((label, from_neighbor, values));
                     }
                 }
-                let final_node = mapping[&proc.final_node];
+                let final_node = mapped_node(graph, mapping, proc.final_node)
+                    .ok_or(RggError::MissingNode(proc.final_node))?;
                 // Remove all affected nodes, then re-add all the required edges.
                 let mut removed = Vec::new();
                 for rule_id in &proc.targets {
                     if *rule_id != proc.final_node {
-                        let node_id = mapping[&rule_id];
+                        let node_id = mapping[rule_id];
                         graph.graph.remove_children(node_id, Some(final_node));
                         graph.remove_node(node_id);
                         removed.push(node_id);
                     }
                 }
-                for neighbor in neighbors {
-                    graph.graph.add_edge(final_node, neighbor).unwrap();
+                for (neighbor, (label, from_neighbor, values)) in neighbors {
+                    // Edges between the merged nodes disappear with them
+                    if !graph.graph.has_node(neighbor) || neighbor == final_node {
+                        continue;
+                    }
+                    let (from, to) = if from_neighbor {
+                        (neighbor, final_node)
+                    } else {
+                        (final_node, neighbor)
+                    };
+                    graph.graph.add_labeled_edge(from, to, label)?;
+                    graph.graph.set_edge_values(from, to, values);
                 }
                 if let Some(a) = ancestor {
                     graph.graph.add_ancestor(final_node, a);
                 }
+                graph.graph.set_node_dirty(final_node);
 
                 ApplyResult::Removed(removed)
             }
-        }
+            Procedure::SetEdge(proc) => {
+                let (from, to) = mapped_edge(graph, mapping, proc.from, proc.to)
+                    .ok_or(RggError::MissingEdge(proc.from, proc.to))?;
+                let variables = variables(graph, mapping, None, env);
+                let values = node::eval_values(
+                    &proc.values,
+                    graph.graph.edge_values(from, to),
+                    &variables,
+                    rng,
+                )?;
+                if let Some(label) = proc.label {
+                    graph.graph.add_labeled_edge(from, to, label)?;
+                }
+                graph.graph.set_edge_values(from, to, values);
+                graph.graph.set_edge_dirty(from, to);
+                ApplyResult::ModifiedEdge(from, to)
+            }
+            Procedure::Connect(proc) => {
+                let from = mapped_node(graph, mapping, proc.from)
+                    .ok_or(RggError::MissingNode(proc.from))?;
+                let to =
+                    mapped_node(graph, mapping, proc.to).ok_or(RggError::MissingNode(proc.to))?;
+                if from == to {
+                    return Err(RggError::Invalid(format!(
+                        "Can't connect node {} to itself",
+                        proc.from
+                    )));
+                }
+                let existed = graph.graph.has_edge(from, to).unwrap_or(false);
+                let variables = variables(graph, mapping, None, env);
+                let values = node::eval_values(&proc.values, None, &variables, rng)?;
+                graph.graph.add_labeled_edge(from, to, proc.label)?;
+                graph.graph.set_edge_values(from, to, values);
+                if existed {
+                    graph.graph.set_edge_dirty(from, to);
+                    ApplyResult::ModifiedEdge(from, to)
+                } else {
+                    ApplyResult::AddedEdge(from, to)
+                }
+            }
+            Procedure::Disconnect(proc) => {
+                let (from, to) = mapped_edge(graph, mapping, proc.from, proc.to)
+                    .ok_or(RggError::MissingEdge(proc.from, proc.to))?;
+                if let Some((_, descendant)) = ancestry(graph, from, to) {
+                    graph.graph.remove_ancestor(descendant);
+                }
+                graph.graph.remove_edge(from, to);
+                ApplyResult::RemovedEdge(from, to)
+            }
+            Procedure::Split(proc) => {
+                let (from, to) = mapped_edge(graph, mapping, proc.from, proc.to)
+                    .ok_or(RggError::MissingEdge(proc.from, proc.to))?;
+                let variables = variables(graph, mapping, Some(from), env);
+                let node = proc
+                    .new_node
+                    .eval(graph.values.get(&from), &variables, rng)?;
+                let label = graph.graph.edge_label(from, to).unwrap_or_default();
+                let values = graph
+                    .graph
+                    .edge_values(from, to)
+                    .cloned()
+                    .unwrap_or_default();
+                let (source, target) = match graph.graph.edge_source(from, to) {
+                    Some(source) if source == to => (to, from),
+                    _ => (from, to),
+                };
+                let ancestry = ancestry(graph, from, to);
+                graph.graph.remove_edge(from, to);
+
+                let node_id = graph.insert_node_with(node);
+                graph.graph.add_labeled_edge(source, node_id, label)?;
+                graph.graph.set_edge_values(source, node_id, values);
+                graph
+                    .graph
+                    .add_labeled_edge(node_id, target, proc.label.unwrap_or(label))?;
+                match ancestry {
+                    Some((ancestor, descendant)) => {
+                        graph.graph.add_ancestor(node_id, ancestor);
+                        graph.graph.add_ancestor(descendant, node_id);
+                    }
+                    None => graph.graph.add_ancestor(node_id, from),
+                }
+                ApplyResult::Split {
+                    node: node_id,
+                    from,
+                    to,
+                }
+            }
+            Procedure::MoveEdge(proc) => {
+                let (a, b) = mapped_edge(graph, mapping, proc.edge.0, proc.edge.1)
+                    .ok_or(RggError::MissingEdge(proc.edge.0, proc.edge.1))?;
+                let c = mapped_node(graph, mapping, proc.to.0)
+                    .ok_or(RggError::MissingNode(proc.to.0))?;
+                let d = mapped_node(graph, mapping, proc.to.1)
+                    .ok_or(RggError::MissingNode(proc.to.1))?;
+                if c == d {
+                    return Err(RggError::Invalid(format!(
+                        "Can't move an edge to connect node {} to itself",
+                        proc.to.0
+                    )));
+                }
+                if graph.graph.has_edge(c, d)? {
+                    return Err(RggError::DuplicateEdge(proc.to.0, proc.to.1));
+                }
+                let label = graph.graph.edge_label(a, b).unwrap_or_default();
+                let values = graph.graph.edge_values(a, b).cloned().unwrap_or_default();
+                let (source, target) = match graph.graph.edge_source(a, b) {
+                    Some(source) if source == b => (d, c),
+                    _ => (c, d),
+                };
+                let ancestry = ancestry(graph, a, b);
+                graph.graph.remove_edge(a, b);
+
+                graph.graph.add_labeled_edge(source, target, label)?;
+                graph.graph.set_edge_values(source, target, values);
+                if let Some((_, descendant)) = ancestry {
+                    if descendant == c {
+                        set_ancestor(graph, c, d);
+                    } else if descendant == d {
+                        set_ancestor(graph, d, c);
+                    } else {
+                        graph.graph.remove_ancestor(descendant);
+                    }
+                }
+                ApplyResult::MovedEdge {
+                    from: (a, b),
+                    to: (c, d),
+                }
+            }
+        };
+        Ok(result)
     }
 }
 
@@ -201,8 +545,7 @@ mod tests {
     use super::*;
 
     use crate::rgg::rgg_graph::RggGraph;
-    use crate::rgg::Node;
-    use gamma::graph::{AppendableGraph, Graph};
+    use gamma::graph::{AppendableGraph, Graph, RemovableGraph};
     use std::collections::HashMap;
 
     /// Gets a triangle graph with all nodes connected, plus its associated mapping
@@ -228,11 +571,32 @@ mod tests {
     #[test]
     fn test_simple_add() {
         let proc = Procedure::Add(AddProcedure {
-            neighbors: vec![0, 1],
-            new_node: Node::new("newnode"),
+            neighbors: vec![
+                0.into(),
+                Neighbor {
+                    id: 1,
+                    label: EdgeLabel::Branch,
+                    values: maplit::btreemap! {
+                        "angle".to_string() => "n1.len * 10".into(),
+                    },
+                },
+            ],
+            new_node: ToNode {
+                name: "newnode".to_string(),
+                values: Default::default(),
+            },
         });
         let (mut graph, mut mapping) = get_simple_graph();
-        proc.apply(&mut graph, &mut mapping);
+        graph.values.get_mut(&1).unwrap().values = maplit::btreemap! {
+            "len".to_string() => Value::Float(3.0),
+        };
+        proc.apply(
+            &mut graph,
+            &mut mapping,
+            &mut rand::thread_rng(),
+            &Environment::default(),
+        )
+        .unwrap();
         assert_eq!(graph.values[&3].name, "newnode");
         let mut neighbors = graph
             .graph
@@ -242,24 +606,295 @@ mod tests {
             .collect::<Vec<_>>();
         neighbors.sort();
         assert_eq!(neighbors, vec![1, 2]);
+        assert!(graph.graph.has_labeled_edge(1, 3, EdgeLabel::Branch));
+        assert_eq!(graph.graph.edge_label(2, 3), Some(EdgeLabel::Plain));
+        assert_eq!(
+            graph.graph.edge_values(1, 3).unwrap()["angle"],
+            Value::Float(30.0)
+        );
+        assert_eq!(graph.graph.edge_values(2, 3), None);
+    }
+
+    #[test]
+    fn test_set_edge() {
+        let proc = Procedure::SetEdge(SetEdgeProcedure {
+            from: 2,
+            to: 1,
+            label: Some(EdgeLabel::Branch),
+            values: maplit::btreemap! {
+                "angle".to_string() => "angle * 2".into(),
+                "stiffness".to_string() => "1".into(),
+            },
+        });
+        let (mut graph, mut mapping) = get_simple_graph();
+        graph.graph.set_edge_values(
+            0,
+            1,
+            maplit::btreemap! {
+                "angle".to_string() => Value::Float(10.0),
+            },
+        );
+        let mut apply = |graph: &mut RggGraph| {
+            proc.apply(
+                graph,
+                &mut mapping,
+                &mut rand::thread_rng(),
+                &Environment::default(),
+            )
+        };
+        assert!(matches!(
+            apply(&mut graph),
+            Ok(ApplyResult::ModifiedEdge(0, 1))
+        ));
+        assert!(graph.graph.has_labeled_edge(0, 1, EdgeLabel::Branch));
+        let values = graph.graph.edge_values(1, 0).unwrap();
+        assert_eq!(values["angle"], Value::Float(20.0));
+        assert_eq!(values["stiffness"], Value::Float(1.0));
+        assert!(matches!(
+            apply(&mut graph),
+            Ok(ApplyResult::ModifiedEdge(0, 1))
+        ));
+        assert_eq!(
+            graph.graph.edge_values(0, 1).unwrap()["angle"],
+            Value::Float(40.0)
+        );
+        // Edges that don't exist can't be set
+        graph.graph.remove_edge(0, 1);
+        assert!(matches!(
+            apply(&mut graph),
+            Err(RggError::MissingEdge(2, 1))
+        ));
+    }
+
+    #[test]
+    fn test_add_uses_matched_nodes() {
+        let proc = Procedure::Add(AddProcedure {
+            neighbors: vec![0.into(), 1.into()],
+            new_node: ToNode {
+                name: "newnode".to_string(),
+                values: maplit::btreemap! {
+                    "len".to_string() => "(n0.len + n1.len) / 2".into(),
+                    "degree".to_string() => "degree".into(),
+                    "depth".to_string() => "depth".into(),
+                    "generation".to_string() => "generation".into(),
+                },
+            },
+        });
+        let (mut graph, mut mapping) = get_simple_graph();
+        graph.graph.add_ancestor(2, 1);
+        graph.values.get_mut(&2).unwrap().values = maplit::btreemap! {
+            "len".to_string() => Value::Float(1.0),
+        };
+        graph.values.get_mut(&1).unwrap().values = maplit::btreemap! {
+            "len".to_string() => Value::Float(3.0),
+        };
+        let env = Environment {
+            generation: 4,
+            ..Default::default()
+        };
+        proc.apply(&mut graph, &mut mapping, &mut rand::thread_rng(), &env)
+            .unwrap();
+        let values = &graph.values[&3].values;
+        assert_eq!(values["len"], Value::Float(2.0));
+        // Based on the first neighbor, host node 2
+        assert_eq!(values["degree"], Value::Float(2.0));
+        assert_eq!(values["depth"], Value::Float(1.0));
+        assert_eq!(values["generation"], Value::Float(4.0));
+    }
+
+    #[test]
+    fn test_connect_and_disconnect() {
+        let (mut graph, mut mapping) = get_simple_graph();
+        graph.graph.add_ancestor(1, 0);
+        let disconnect = Procedure::Disconnect(DisconnectProcedure { from: 2, to: 1 });
+        let connect = Procedure::Connect(ConnectProcedure {
+            from: 1,
+            to: 2,
+            label: EdgeLabel::Branch,
+            values: maplit::btreemap! {"angle".to_string() => "30".into()},
+        });
+        let mut apply = |graph: &mut RggGraph, proc: &Procedure| {
+            proc.apply(
+                graph,
+                &mut mapping,
+                &mut rand::thread_rng(),
+                &Environment::default(),
+            )
+        };
+        assert!(matches!(
+            apply(&mut graph, &disconnect),
+            Ok(ApplyResult::RemovedEdge(0, 1))
+        ));
+        assert!(!graph.graph.has_edge(0, 1).unwrap());
+        assert_eq!(graph.graph.get_ancestor(1), None);
+        assert!(matches!(
+            apply(&mut graph, &disconnect),
+            Err(RggError::MissingEdge(2, 1))
+        ));
+
+        assert!(matches!(
+            apply(&mut graph, &connect),
+            Ok(ApplyResult::AddedEdge(1, 0))
+        ));
+        assert!(graph.graph.has_labeled_edge(1, 0, EdgeLabel::Branch));
+        assert_eq!(
+            graph.graph.edge_values(0, 1).unwrap()["angle"],
+            Value::Float(30.0)
+        );
+        // Connecting again only changes the edge
+        assert!(matches!(
+            apply(&mut graph, &connect),
+            Ok(ApplyResult::ModifiedEdge(1, 0))
+        ));
+        assert!(matches!(
+            apply(&mut graph, &disconnect),
+            Ok(ApplyResult::RemovedEdge(0, 1))
+        ));
+    }
+
+    #[test]
+    fn test_split() {
+        let mut graph = RggGraph::new();
+        let stem = graph.insert_node_with(crate::rgg::Node::new("stem"));
+        let tip = graph.insert_node_with(crate::rgg::Node::new("tip"));
+        graph
+            .graph
+            .add_labeled_edge(stem, tip, EdgeLabel::Branch)
+            .unwrap();
+        graph.graph.set_edge_values(
+            stem,
+            tip,
+            maplit::btreemap! {"angle".to_string() => Value::Float(45.0)},
+        );
+        graph.graph.add_ancestor(tip, stem);
+        graph.graph.advance_generation();
+        // Split against the direction of the edge
+        let mut mapping = maplit::hashmap! {0 => tip, 1 => stem};
+        let proc = Procedure::Split(SplitProcedure {
+            from: 0,
+            to: 1,
+            new_node: ToNode {
+                name: "internode".to_string(),
+                values: Default::default(),
+            },
+            label: Some(EdgeLabel::Successor),
+        });
+        let node = match proc.apply(
+            &mut graph,
+            &mut mapping,
+            &mut rand::thread_rng(),
+            &Environment::default(),
+        ) {
+            Ok(ApplyResult::Split { node, from, to }) => {
+                assert_eq!((from, to), (tip, stem));
+                node
+            }
+            _ => panic!("Edge was not split"),
+        };
+        assert_eq!(graph.values[&node].name, "internode");
+        assert!(!graph.graph.has_edge(stem, tip).unwrap());
+        assert!(graph.graph.has_labeled_edge(stem, node, EdgeLabel::Branch));
+        assert!(graph
+            .graph
+            .has_labeled_edge(node, tip, EdgeLabel::Successor));
+        assert_eq!(
+            graph.graph.edge_values(stem, node).unwrap()["angle"],
+            Value::Float(45.0)
+        );
+        assert_eq!(graph.graph.edge_values(node, tip), None);
+        assert_eq!(graph.graph.get_ancestor(node), Some(stem));
+        assert_eq!(graph.graph.get_ancestor(tip), Some(node));
+        assert_eq!(graph.graph.get_children(stem), vec![node]);
+    }
+
+    #[test]
+    fn test_move_edge() {
+        let (mut graph, mut mapping) = get_simple_graph();
+        let shoot = graph.insert_node();
+        graph
+            .graph
+            .add_labeled_edge(0, shoot, EdgeLabel::Branch)
+            .unwrap();
+        graph.graph.add_ancestor(shoot, 0);
+        graph.graph.advance_generation();
+        mapping.insert(3, shoot);
+        let mut apply = |graph: &mut RggGraph, edge, to| {
+            let proc = Procedure::MoveEdge(MoveEdgeProcedure { edge, to });
+            proc.apply(
+                graph,
+                &mut mapping,
+                &mut rand::thread_rng(),
+                &Environment::default(),
+            )
+        };
+        // Reattach the shoot from host node 0 to 1
+        assert!(matches!(
+            apply(&mut graph, (2, 3), (1, 3)),
+            Ok(ApplyResult::MovedEdge {
+                from: (0, 3),
+                to: (1, 3)
+            })
+        ));
+        assert!(!graph.graph.has_edge(0, shoot).unwrap());
+        assert!(graph.graph.has_labeled_edge(1, shoot, EdgeLabel::Branch));
+        assert_eq!(graph.graph.get_ancestor(shoot), Some(1));
+        // Edges can't be moved onto existing edges
+        graph.graph.advance_generation();
+        assert!(matches!(
+            apply(&mut graph, (1, 3), (1, 0)),
+            Err(RggError::DuplicateEdge(1, 0))
+        ));
     }
 
     #[test]
     fn test_simple_delete() {
         let proc = Procedure::Delete(DeleteProcedure { target: 2 });
         let (mut graph, mut mapping) = get_simple_graph();
-        proc.apply(&mut graph, &mut mapping);
+        proc.apply(
+            &mut graph,
+            &mut mapping,
+            &mut rand::thread_rng(),
+            &Environment::default(),
+        )
+        .unwrap();
         assert_eq!(graph.graph.order(), 2, "Contents {:?}", graph.graph);
         assert_eq!(graph.values.len(), 2, "Contents {:?}", graph.values);
     }
 
     #[test]
-    fn test_dont_touch_dirty() {
+    fn test_delete_keeps_ancestry() {
+        let proc = Procedure::Delete(DeleteProcedure { target: 1 });
+        let (mut graph, mut mapping) = get_simple_graph();
+        graph.graph.add_ancestor(1, 0);
+        graph.graph.add_ancestor(2, 1);
+        proc.apply(
+            &mut graph,
+            &mut mapping,
+            &mut rand::thread_rng(),
+            &Environment::default(),
+        )
+        .unwrap();
+        assert_eq!(graph.graph.get_ancestor(2), Some(0));
+        assert_eq!(graph.graph.get_children(0), vec![2]);
+        assert!(graph.graph.get_children(1).is_empty());
+    }
+
+    #[test]
+    fn test_rewrite_dirty() {
+        // Whether a node is still clean is checked by the match before any procedure is applied,
+        // so that a match can delete a node it has just added or changed
         let proc = Procedure::Delete(DeleteProcedure { target: 0 });
         let mut graph = RggGraph::new();
         graph.insert_node();
+        assert!(graph.graph.node_is_dirty(0));
         let mut mapping = maplit::hashmap! { 0 => 0 };
-        proc.apply(&mut graph, &mut mapping);
-        assert_eq!(graph.graph.order(), 1, "Contents {:?}", graph.graph);
+        proc.apply(
+            &mut graph,
+            &mut mapping,
+            &mut rand::thread_rng(),
+            &Environment::default(),
+        )
+        .unwrap();
+        assert_eq!(graph.graph.order(), 0, "Contents {:?}", graph.graph);
     }
 }
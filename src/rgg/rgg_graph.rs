@@ -1,36 +1,103 @@
 use crate::rgg::dirty_graph::DirtyGraph;
-use crate::rgg::Node;
+use crate::rgg::{Node, Value};
 use gamma::graph::{AppendableGraph, Graph, RemovableGraph};
-use std::collections::HashMap;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct RggGraph {
     pub graph: DirtyGraph,
-    pub values: HashMap<usize, super::Node>,
+    /// Change these through the methods of the graph, so that transactions can undo the changes.
+    pub values: BTreeMap<usize, super::Node>,
+    /// The values of the nodes that the current transaction changed, from before it changed them.
+    journal: Option<BTreeMap<usize, Option<Node>>>,
 }
 
 impl RggGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
     pub fn insert_node(&mut self) -> usize {
-        let n = self.graph.add_node().unwrap();
-        self.values.insert(n, Node::new(""));
-        n
+        self.insert_node_with(Node::new(""))
     }
 
     pub fn insert_node_with(&mut self, node: Node) -> usize {
-        let n = self.graph.add_node().unwrap();
-        self.values.insert(n, node);
+        let n = self
+            .graph
+            .add_node()
+            .expect("DirtyGraph always finds an unused id");
+        self.set_node(n, node);
         n
     }
 
     pub fn remove_node(&mut self, id: usize) {
+        self.touch(id);
         self.graph.remove_node(id);
         self.values.remove_entry(&id);
     }
 
+    /// Replace the name and values of a node, returning the old ones.
+    pub fn set_node(&mut self, id: usize, node: Node) -> Option<Node> {
+        self.touch(id);
+        self.values.insert(id, node)
+    }
+
+    /// Remember the values of a node before the current transaction changes them.
+    fn touch(&mut self, id: usize) {
+        if let Some(journal) = &mut self.journal {
+            if let Entry::Vacant(entry) = journal.entry(id) {
+                entry.insert(self.values.get(&id).cloned());
+            }
+        }
+    }
+
+    /// Start recording changes to the graph and the values of its nodes, so that they can be
+    /// undone with `rollback`. Transactions can't be nested.
+    pub fn begin_transaction(&mut self) {
+        self.graph.begin_transaction();
+        if self.journal.is_none() {
+            self.journal = Some(BTreeMap::new());
+        }
+    }
+
+    /// Keep the changes of the current transaction.
+    pub fn commit(&mut self) {
+        self.graph.commit();
+        self.journal = None;
+    }
+
+    /// Undo all changes since the current transaction began.
+    pub fn rollback(&mut self) {
+        self.graph.rollback();
+        for (id, node) in self.journal.take().unwrap_or_default() {
+            match node {
+                Some(node) => self.values.insert(id, node),
+                None => self.values.remove(&id),
+            };
+        }
+    }
+
     pub fn order(&self) -> usize {
         self.graph.order()
     }
 
+    /// The number of ancestors between the node and the root of its tree.
+    pub fn depth(&self, id: usize) -> usize {
+        let mut depth = 0;
+        let mut current = id;
+        while let Some(ancestor) = self.graph.get_ancestor(current) {
+            depth += 1;
+            current = ancestor;
+            // Ancestors should never form a cycle, but don't loop forever if they do
+            if depth > self.order() {
+                log::warn!("Node {} has cyclic ancestors", id);
+                break;
+            }
+        }
+        depth
+    }
+
     pub fn neighbors<'a>(
         &'a self,
         id: usize,
@@ -50,9 +117,228 @@ impl RggGraph {
             strings.push(format!(r#"  {} [label="{}"]"#, *id, name));
         }
         for (from, to) in self.graph.edges() {
-            strings.push(format!(r#"  {} -- {}"#, *from, *to));
+            match self.graph.edge_label(*from, *to) {
+                Some(label) if label.is_directed() => {
+                    let (from, to) = match self.graph.edge_source(*from, *to) {
+                        Some(source) if source == *to => (to, from),
+                        _ => (from, to),
+                    };
+                    strings.push(format!(
+                        r#"  {} -- {} [label="{}" dir=forward]"#,
+                        *from,
+                        *to,
+                        label.symbol()
+                    ));
+                }
+                _ => strings.push(format!(r#"  {} -- {}"#, *from, *to)),
+            }
         }
         strings.push("}".to_string());
         strings.join("\n")
     }
+
+    /// Hash the nodes, their values, the edges and the ancestors of the graph.
+    /// Unlike std's hashers, the result is the same across runs, platforms and compiler versions,
+    /// so it can be stored to check that a simulation is reproducible.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write_usize(self.graph.order());
+        for id in self.graph.nodes() {
+            hash.write_usize(*id);
+            if let Some(node) = self.values.get(id) {
+                hash.write_str(&node.name);
+                hash.write_usize(node.values.len());
+                for (name, value) in &node.values {
+                    hash.write_str(name);
+                    hash.write_value(value);
+                }
+            }
+            match self.graph.get_ancestor(*id) {
+                Some(ancestor) => {
+                    hash.write(&[1]);
+                    hash.write_usize(ancestor);
+                }
+                None => hash.write(&[0]),
+            }
+        }
+        hash.write_usize(self.graph.size());
+        for (from, to) in self.graph.edges() {
+            hash.write_usize(*from);
+            hash.write_usize(*to);
+            // Plain edges are hashed as they were before edges had labels
+            if let Some(source) = self.graph.edge_source(*from, *to) {
+                hash.write(&[self.graph.edge_label(*from, *to).unwrap_or_default() as u8]);
+                hash.write_usize(source);
+            }
+            // As are edges without values
+            if let Some(values) = self.graph.edge_values(*from, *to) {
+                hash.write(&[0xff]);
+                hash.write_usize(values.len());
+                for (name, value) in values {
+                    hash.write_str(name);
+                    hash.write_value(value);
+                }
+            }
+        }
+        hash.0
+    }
+}
+
+/// 64-bit FNV-1a, fed with explicitly little-endian data.
+pub(crate) struct Fnv(u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn write_usize(&mut self, n: usize) {
+        self.write(&(n as u64).to_le_bytes());
+    }
+
+    pub(crate) fn write_str(&mut self, s: &str) {
+        self.write_usize(s.len());
+        self.write(s.as_bytes());
+    }
+
+    pub(crate) fn write_value(&mut self, value: &Value) {
+        match value {
+            Value::Int(i) => {
+                self.write(&[0]);
+                self.write(&i.to_le_bytes());
+            }
+            Value::Float(f) => {
+                self.write(&[1]);
+                self.write(&f.to_bits().to_le_bytes());
+            }
+            Value::Bool(b) => self.write(&[2, *b as u8]),
+            Value::String(s) => {
+                self.write(&[3]);
+                self.write_str(s);
+            }
+            Value::Vec3(v) => {
+                self.write(&[4]);
+                for f in v {
+                    self.write(&f.to_bits().to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgg::EdgeLabel;
+
+    #[test]
+    fn test_fingerprint() {
+        let mut graph = RggGraph::new();
+        let empty = graph.fingerprint();
+        let a = graph.insert_node_with(Node::new("stem"));
+        let b = graph.insert_node_with(Node::new("stem"));
+        let unconnected = graph.fingerprint();
+        graph.graph.add_edge(a, b).unwrap();
+        let connected = graph.fingerprint();
+        assert_ne!(empty, unconnected);
+        assert_ne!(unconnected, connected);
+        assert_eq!(connected, graph.fingerprint());
+
+        graph
+            .values
+            .get_mut(&b)
+            .unwrap()
+            .values
+            .insert("len".to_string(), Value::Float(1.0));
+        let float = graph.fingerprint();
+        graph
+            .values
+            .get_mut(&b)
+            .unwrap()
+            .values
+            .insert("len".to_string(), Value::Int(1));
+        assert_ne!(connected, float);
+        assert_ne!(float, graph.fingerprint());
+
+        let int = graph.fingerprint();
+        graph
+            .graph
+            .add_labeled_edge(a, b, EdgeLabel::Branch)
+            .unwrap();
+        let branch = graph.fingerprint();
+        graph
+            .graph
+            .add_labeled_edge(b, a, EdgeLabel::Branch)
+            .unwrap();
+        assert_ne!(int, branch);
+        assert_ne!(branch, graph.fingerprint());
+
+        let reversed = graph.fingerprint();
+        graph.graph.set_edge_values(
+            a,
+            b,
+            maplit::btreemap! {"angle".to_string() => Value::Int(1)},
+        );
+        assert_ne!(reversed, graph.fingerprint());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut graph = RggGraph::new();
+        let a = graph.insert_node_with(Node::new("stem"));
+        let b = graph.insert_node_with(Node::new("stem"));
+        let c = graph.insert_node_with(Node::new("shoot"));
+        graph
+            .graph
+            .add_labeled_edge(a, b, EdgeLabel::Successor)
+            .unwrap();
+        graph.graph.add_edge(b, c).unwrap();
+        graph.graph.add_ancestor(b, a);
+        graph.graph.add_ancestor(c, b);
+        graph.graph.advance_generation();
+        let before = format!("{:?}", graph);
+
+        graph.begin_transaction();
+        graph.set_node(a, Node::new("root"));
+        graph.remove_node(b);
+        let d = graph.insert_node_with(Node::new("leaf"));
+        graph
+            .graph
+            .add_labeled_edge(c, d, EdgeLabel::Branch)
+            .unwrap();
+        graph.graph.set_edge_values(
+            c,
+            d,
+            maplit::btreemap! {"angle".to_string() => Value::Int(1)},
+        );
+        graph.graph.add_ancestor(c, a);
+        graph.graph.add_ancestor(d, c);
+        graph.graph.set_node_dirty(c);
+        assert!(graph.graph.in_transaction());
+        graph.rollback();
+
+        assert!(!graph.graph.in_transaction());
+        assert_eq!(format!("{:?}", graph), before);
+        // Node ids are handed out again
+        assert_eq!(graph.insert_node(), d);
+
+        // Committed changes stay
+        graph.begin_transaction();
+        graph.remove_node(d);
+        graph.commit();
+        graph.rollback();
+        assert!(!graph.graph.has_node(d));
+        assert!(!graph.values.contains_key(&d));
+    }
 }
@@ -1,42 +1,178 @@
 use super::{FromNode, RggGraph};
+use crate::rgg::expr::{self, Variables};
+use crate::rgg::node::ExprError;
 use crate::rgg::procedures::{ApplyResult, Procedure};
-use gamma::graph::{AppendableGraph, DefaultGraph};
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::rgg::{Environment, PatternEdge, RggError};
+use gamma::graph::{AppendableGraph, DefaultGraph, Graph};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{Duration, Instant};
 
 /// A defined node in a ruleset. Has an optional name, and may have edge connections.
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct NodeSet {
+    #[serde(default)]
     pub nodes: Vec<FromNode>,
     #[serde(default)]
-    pub edges: Vec<(i32, i32)>,
+    pub edges: Vec<PatternEdge>,
+    /// Negative application conditions: a match is rejected if any of these can be found around it.
+    /// They may refer to the ids of the enclosing pattern in their edges, and those nodes stay fixed
+    /// to the match while looking for the forbidden nodes.
+    #[serde(default)]
+    pub not: Vec<NodeSet>,
 }
 
 impl NodeSet {
     /// Encode the node & edge relations as a graph
-    pub fn as_graph(&self) -> DefaultGraph {
+    pub fn as_graph(&self) -> Result<DefaultGraph, RggError> {
         let mut graph = DefaultGraph::new();
         for node in &self.nodes {
-            graph
-                .add_node_with(node.id as usize)
-                .unwrap_or_else(|e| panic!("{:?}", e));
+            graph.add_node_with(node.id as usize)?;
         }
 
         for edge in &self.edges {
-            graph
-                .add_edge(edge.0 as usize, edge.1 as usize)
-                .unwrap_or_else(|e| panic!("{:?}", e));
+            graph.add_edge(edge.from as usize, edge.to as usize)?;
         }
 
-        graph
+        Ok(graph)
+    }
+
+    /// Check that every edge connects nodes of the pattern, or nodes of the enclosing patterns
+    /// given in `outer`. Negative conditions are checked as well.
+    pub fn check_edges(&self, outer: &BTreeSet<i32>) -> Result<(), RggError> {
+        let mut known = outer.clone();
+        known.extend(self.nodes.iter().map(|node| node.id));
+        for edge in &self.edges {
+            for id in &[edge.from, edge.to] {
+                if !known.contains(id) {
+                    return Err(RggError::Invalid(format!(
+                        "Edge ({}, {}) refers to node {}, which is not in the pattern",
+                        edge.from, edge.to, id
+                    )));
+                }
+            }
+        }
+        for forbidden in &self.not {
+            forbidden.check_edges(&known)?;
+        }
+        Ok(())
+    }
+
+    /// Check that every parameter the conditions on nodes and edges refer to exists.
+    /// Negative conditions are checked as well.
+    pub fn check_parameters(&self, parameters: &Variables) -> Result<(), RggError> {
+        let node_conditions = self.nodes.iter().flat_map(|node| &node.values);
+        let edge_conditions = self.edges.iter().flat_map(|edge| &edge.values);
+        for (name, condition) in node_conditions.chain(edge_conditions) {
+            if let Some(unknown) = condition
+                .parameters()
+                .into_iter()
+                .find(|parameter| !parameters.contains_key(*parameter))
+            {
+                return Err(RggError::Invalid(format!(
+                    "The condition on value {:?} refers to unknown parameter {:?}",
+                    name, unknown
+                )));
+            }
+        }
+        for forbidden in &self.not {
+            forbidden.check_parameters(parameters)?;
+        }
+        Ok(())
     }
 }
 
+/// One of several possible right-hand sides of a rule.
+#[derive(Deserialize, Clone)]
+pub struct Alternative {
+    /// How likely this alternative is to be picked, relative to the other alternatives.
+    pub weight: f64,
+    pub to: Vec<Procedure>,
+}
+
+fn default_probability() -> f64 {
+    1.0
+}
+
 /// Describes a replacement rule.
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Rule {
     pub from: NodeSet,
+    #[serde(default)]
     pub to: Vec<Procedure>,
+    /// The chance that the rule is applied to any given match.
+    #[serde(default = "default_probability")]
+    pub probability: f64,
+    /// If given, one of these is picked at random for every match and used instead of `to`.
+    #[serde(default)]
+    pub alternatives: Vec<Alternative>,
+    /// When rules are applied in parallel, matches of rules with a higher priority can win conflicts.
+    #[serde(default)]
+    pub priority: i32,
+    /// An expression over the values of the matched nodes that must hold for the rule to apply,
+    /// e.g. `n0.len + n1.len > 3 && n0.age < n1.age`.
+    #[serde(default)]
+    pub when: Option<String>,
+}
+
+/// A match of a rule, together with the procedures that were picked to apply to it.
+pub struct RuleMatch<'a> {
+    pub mapping: HashMap<i32, usize>,
+    pub procedures: &'a [Procedure],
+}
+
+/// A match whose procedures could not all be applied, so that none of them were.
+#[derive(Debug)]
+pub struct RuleFailure {
+    pub mapping: HashMap<i32, usize>,
+    /// The index of the procedure that failed.
+    pub procedure: usize,
+    pub error: RggError,
+}
+
+/// A match that a rule was applied to, and what that changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppliedMatch {
+    /// The position of the rule, as for the statistics.
+    pub rule: usize,
+    pub mapping: BTreeMap<i32, usize>,
+    pub removed: Vec<usize>,
+    pub added: Vec<usize>,
+    pub modified: Vec<usize>,
+    pub modified_edges: Vec<(usize, usize)>,
+    pub added_edges: Vec<(usize, usize)>,
+    pub removed_edges: Vec<(usize, usize)>,
+}
+
+/// How much work a rule did, for profiling and debugging grammars.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleStats {
+    /// Matches that satisfied the rule's guard.
+    pub matches: usize,
+    /// Matches that were not applied because they conflicted with other matches, or because
+    /// nodes or edges they needed were removed or rewritten by earlier matches.
+    pub skipped: usize,
+    /// Procedures that changed the graph, not counting those of rolled back matches.
+    pub applied: usize,
+    /// Procedures that failed, each of which rolled back its match.
+    pub failed: usize,
+    /// Time spent finding and selecting matches.
+    pub matching: Duration,
+    /// Time spent applying procedures.
+    pub applying: Duration,
+}
+
+impl RuleStats {
+    pub fn add(&mut self, stats: &Self) {
+        self.matches += stats.matches;
+        self.skipped += stats.skipped;
+        self.applied += stats.applied;
+        self.failed += stats.failed;
+        self.matching += stats.matching;
+        self.applying += stats.applying;
+    }
 }
 
 /// Tracks the results of executing the entire rule
@@ -45,6 +181,16 @@ pub struct RuleResult {
     pub removed: Vec<usize>,
     pub added: Vec<usize>,
     pub modified: Vec<usize>,
+    /// Edges whose label or values changed, as the nodes at both ends.
+    pub modified_edges: Vec<(usize, usize)>,
+    pub added_edges: Vec<(usize, usize)>,
+    pub removed_edges: Vec<(usize, usize)>,
+    /// Matches that were rolled back because one of their procedures failed.
+    pub failed: Vec<RuleFailure>,
+    /// The statistics of every rule, by its position in the rules that were applied.
+    pub stats: Vec<RuleStats>,
+    /// Every match that was applied, in order.
+    pub applied: Vec<AppliedMatch>,
 }
 
 impl RuleResult {
@@ -53,6 +199,20 @@ impl RuleResult {
             removed: Vec::new(),
             added: Vec::new(),
             modified: Vec::new(),
+            modified_edges: Vec::new(),
+            added_edges: Vec::new(),
+            removed_edges: Vec::new(),
+            failed: Vec::new(),
+            stats: Vec::new(),
+            applied: Vec::new(),
+        }
+    }
+
+    /// A result that changed nothing, with the statistics of a single rule.
+    pub fn with_stats(stats: RuleStats) -> Self {
+        Self {
+            stats: vec![stats],
+            ..Self::new()
         }
     }
     pub fn add_apply_result(&mut self, apply: ApplyResult) {
@@ -60,44 +220,631 @@ impl RuleResult {
             ApplyResult::Removed(r) => self.removed.extend(r),
             ApplyResult::Added(a) => self.added.push(a),
             ApplyResult::Modified(m) => self.modified.push(m),
-            _ => {}
+            ApplyResult::ModifiedEdge(a, b) => self.modified_edges.push((a, b)),
+            ApplyResult::AddedEdge(a, b) => self.added_edges.push((a, b)),
+            ApplyResult::RemovedEdge(a, b) => self.removed_edges.push((a, b)),
+            ApplyResult::Split { node, from, to } => {
+                self.added.push(node);
+                self.removed_edges.push((from, to));
+                self.added_edges.push((from, node));
+                self.added_edges.push((node, to));
+            }
+            ApplyResult::MovedEdge { from, to } => {
+                self.removed_edges.push(from);
+                self.added_edges.push(to);
+            }
         }
     }
 
+    /// Whether nothing was changed. Failures don't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+            && self.added.is_empty()
+            && self.modified.is_empty()
+            && self.modified_edges.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+    }
+
     pub fn add(&mut self, result: Self) {
+        self.add_at(0, result);
+    }
+
+    /// Add the result of applying some rules, the first of which is at position `first_rule`.
+    pub fn add_at(&mut self, first_rule: usize, result: Self) {
+        if self.stats.len() < first_rule + result.stats.len() {
+            self.stats
+                .resize(first_rule + result.stats.len(), RuleStats::default());
+        }
+        for (total, stats) in self.stats[first_rule..].iter_mut().zip(&result.stats) {
+            total.add(stats);
+        }
         self.removed.extend(result.removed);
         self.added.extend(result.added);
         self.modified.extend(result.modified);
+        self.modified_edges.extend(result.modified_edges);
+        self.added_edges.extend(result.added_edges);
+        self.removed_edges.extend(result.removed_edges);
+        self.failed.extend(result.failed);
+        self.applied
+            .extend(result.applied.into_iter().map(|mut applied| {
+                applied.rule += first_rule;
+                applied
+            }));
     }
 }
 
 impl Rule {
+    /// Read a single rule from YAML.
+    pub fn from_yaml(yaml: &str) -> Result<Self, RggError> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Create a rule that always applies the given procedures.
+    pub fn new(from: NodeSet, to: Vec<Procedure>) -> Self {
+        Self {
+            from,
+            to,
+            probability: 1.0,
+            alternatives: vec![],
+            priority: 0,
+            when: None,
+        }
+    }
+
     /// Find all match and apply the rule to each match.
     /// If a node or edge disappears during applying a rule, it is skipped.
-    pub fn apply(&self, graph: &mut RggGraph) -> RuleResult {
-        let matches = self.matches(graph).collect::<Vec<_>>();
+    /// All random decisions are drawn from `rng`, in match order.
+    /// Fails if the rule is malformed. Matches that can't be applied are reported in the result.
+    pub fn apply(
+        &self,
+        graph: &mut RggGraph,
+        rng: &mut dyn RngCore,
+        env: &Environment,
+    ) -> Result<RuleResult, RggError> {
+        let start = Instant::now();
+        let matches = self.guarded_matches(graph, env)?;
+        let mut stats = RuleStats {
+            matches: matches.len(),
+            matching: start.elapsed(),
+            ..Default::default()
+        };
+        let mut result = RuleResult::new();
+        for mapping in matches {
+            let start = Instant::now();
+            let selected = self.select(mapping, rng)?;
+            stats.matching += start.elapsed();
+            if let Some(selected) = selected {
+                result.add(selected.apply(graph, rng, env));
+            }
+        }
+        result.add(RuleResult::with_stats(stats));
+
+        Ok(result)
+    }
+
+    /// Find all matches, and decide which of them the rule is applied to and how,
+    /// without changing the graph. Also returns how many matches there were and how long it took.
+    pub fn select_matches(
+        &self,
+        graph: &RggGraph,
+        rng: &mut dyn RngCore,
+        env: &Environment,
+    ) -> Result<(Vec<RuleMatch<'_>>, RuleStats), RggError> {
+        let start = Instant::now();
+        let mut stats = RuleStats::default();
+        let mut selected = vec![];
+        for mapping in self.guarded_matches(graph, env)? {
+            stats.matches += 1;
+            selected.extend(self.select(mapping, rng)?);
+        }
+        stats.matching = start.elapsed();
+        Ok((selected, stats))
+    }
+
+    /// Find all matches that satisfy the rule's guard. Fails if the guard can't be evaluated.
+    pub fn guarded_matches(
+        &self,
+        graph: &RggGraph,
+        env: &Environment,
+    ) -> Result<Vec<HashMap<i32, usize>>, RggError> {
+        let mut matches = vec![];
+        for mapping in self.matches(graph, env)? {
+            if self.check_guard(graph, &mapping, env)? {
+                matches.push(mapping);
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Evaluate the guard against the values of the matched nodes. Fails if the guard can't be evaluated.
+    fn check_guard(
+        &self,
+        graph: &RggGraph,
+        mapping: &HashMap<i32, usize>,
+        env: &Environment,
+    ) -> Result<bool, RggError> {
+        let guard = match &self.when {
+            Some(guard) => guard,
+            None => return Ok(true),
+        };
+        let mut variables = Variables::new();
+        for (rule_id, id) in mapping {
+            if let Some(node) = graph.values.get(id) {
+                expr::add_values(&mut variables, Some(*rule_id), &node.values);
+            }
+        }
+        env.add_variables(&mut variables);
+        expr::eval_bool(guard, &expr::new_context(&variables)).map_err(|error| {
+            RggError::Expr(ExprError::Eval {
+                name: "when".to_string(),
+                expr: guard.clone(),
+                error,
+            })
+        })
+    }
+
+    /// Decide whether the rule is applied to a match, and pick the procedures to apply.
+    fn select(
+        &self,
+        mapping: HashMap<i32, usize>,
+        rng: &mut dyn RngCore,
+    ) -> Result<Option<RuleMatch<'_>>, RggError> {
+        if self.probability < 1.0 && rng.gen::<f64>() >= self.probability {
+            log::debug!("Rule was not applied to {:?} by chance.", mapping);
+            return Ok(None);
+        }
+        let procedures = self.choose_procedures(rng)?;
+        Ok(Some(RuleMatch {
+            mapping,
+            procedures,
+        }))
+    }
+
+    /// Pick the procedures to apply to a single match.
+    fn choose_procedures(&self, rng: &mut dyn RngCore) -> Result<&[Procedure], RggError> {
+        if self.alternatives.is_empty() {
+            return Ok(&self.to);
+        }
+        let weights =
+            WeightedIndex::new(self.alternatives.iter().map(|alt| alt.weight)).map_err(|e| {
+                RggError::Invalid(format!(
+                    "Could not pick one of the rule's alternatives: {}",
+                    e
+                ))
+            })?;
+        Ok(&self.alternatives[weights.sample(rng)].to)
+    }
+}
+
+impl RuleMatch<'_> {
+    /// Apply the picked procedures to the match, as a single transaction: if any procedure fails,
+    /// the changes of the others are undone and the failure is reported in the result.
+    /// If a node or edge disappears during applying a rule, or one that would be rewritten was
+    /// already rewritten in this generation, the whole match is skipped. Later procedures of the
+    /// match may rewrite what its earlier ones changed.
+    /// The result holds the statistics of the match's rule.
+    pub fn apply(
+        self,
+        graph: &mut RggGraph,
+        rng: &mut dyn RngCore,
+        env: &Environment,
+    ) -> RuleResult {
+        let start = Instant::now();
+        let mut stats = RuleStats::default();
+        if !self.check_procedure_targets_exist(graph) {
+            log::debug!("Some targets for Rule apply did not exist and were skipped.");
+            stats.skipped = 1;
+            return RuleResult::with_stats(stats);
+        }
+        if !self.rewritten_are_clean(graph) {
+            log::debug!(
+                "Skipped match {:?} as it would rewrite dirty nodes or edges",
+                self.mapping
+            );
+            stats.skipped = 1;
+            return RuleResult::with_stats(stats);
+        }
         let mut result = RuleResult::new();
-        for mut mapping in matches {
-            if self.check_procedure_targets_exist(&mapping) {
-                for procedure in &self.to {
-                    let apply_result = procedure.apply(graph, &mut mapping);
+        // Procedures may change the mapping, but a failure should report the match as it was
+        let mut mapping = self.mapping.clone();
+        graph.begin_transaction();
+        for (index, procedure) in self.procedures.iter().enumerate() {
+            match procedure.apply(graph, &mut mapping, rng, env) {
+                Ok(apply_result) => {
+                    stats.applied += 1;
                     result.add_apply_result(apply_result);
                 }
-            } else {
-                log::debug!("Some targets for Rule apply did not exist and were skipped.");
+                Err(error) => {
+                    graph.rollback();
+                    log::debug!("Rolled back match {:?}: {}", self.mapping, error);
+                    let mut failed = RuleResult::with_stats(RuleStats {
+                        failed: 1,
+                        applied: 0,
+                        applying: start.elapsed(),
+                        ..stats
+                    });
+                    failed.failed.push(RuleFailure {
+                        mapping: self.mapping,
+                        procedure: index,
+                        error,
+                    });
+                    return failed;
+                }
             }
         }
-
+        graph.commit();
+        result.applied.push(AppliedMatch {
+            rule: 0,
+            mapping: self.mapping.into_iter().collect(),
+            removed: result.removed.clone(),
+            added: result.added.clone(),
+            modified: result.modified.clone(),
+            modified_edges: result.modified_edges.clone(),
+            added_edges: result.added_edges.clone(),
+            removed_edges: result.removed_edges.clone(),
+        });
+        stats.applying = start.elapsed();
+        result.add(RuleResult::with_stats(stats));
         result
     }
 
+    /// The host nodes that the procedures delete or change.
+    pub fn rewritten_nodes(&self) -> BTreeSet<usize> {
+        self.procedures
+            .iter()
+            .flat_map(|procedure| procedure.rewritten_targets())
+            .filter_map(|target| self.mapping.get(&target).copied())
+            .collect()
+    }
+
+    /// Check that none of the nodes and edges that the procedures rewrite were rewritten in this
+    /// generation, so that either all procedures apply or none.
+    fn rewritten_are_clean(&self, graph: &RggGraph) -> bool {
+        let nodes_clean = self
+            .rewritten_nodes()
+            .iter()
+            .all(|id| !graph.graph.node_is_dirty(*id));
+        let edges_clean = self
+            .procedures
+            .iter()
+            .flat_map(|procedure| procedure.rewritten_edges())
+            .filter_map(|(from, to)| Some((*self.mapping.get(&from)?, *self.mapping.get(&to)?)))
+            .all(|(from, to)| {
+                !(graph.graph.has_edge(from, to).unwrap_or(false)
+                    && graph.graph.edge_is_dirty(from, to))
+            });
+        nodes_clean && edges_clean
+    }
+
     /// Check that all procedure targets exist before attempting to run any procedure.
-    fn check_procedure_targets_exist(&self, mapping: &HashMap<i32, usize>) -> bool {
-        for proc in &self.to {
-            if !proc.targets_exist(mapping) {
+    fn check_procedure_targets_exist(&self, graph: &RggGraph) -> bool {
+        for proc in self.procedures {
+            if !proc.targets_exist(&self.mapping) {
                 return false;
             }
         }
-        true
+        // Earlier matches may have removed matched nodes
+        self.mapping
+            .values()
+            .all(|host| graph.values.contains_key(host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Grow a chain of stems from a single stem, where each stem has a random length.
+    fn grow(rule: &Rule, seed: u64, steps: usize) -> RggGraph {
+        let mut graph = RggGraph::new();
+        graph.insert_node_with(crate::rgg::Node::new("stem"));
+        let mut rng = StdRng::seed_from_u64(seed);
+        for _ in 0..steps {
+            graph.graph.advance_generation();
+            rule.apply(&mut graph, &mut rng, &Environment::default())
+                .unwrap();
+        }
+        graph
+    }
+
+    fn serialized(graph: &RggGraph) -> String {
+        serde_yaml::to_string(graph).unwrap()
+    }
+
+    #[test]
+    fn test_seeded_apply_is_reproducible() {
+        let rule: Rule = serde_yaml::from_str(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}]
+probability: 0.5
+alternatives:
+  - weight: 3
+    to:
+      - add: {neighbors: [0], node: {name: stem, values: {len: "rand(0, 1)"}}}
+  - weight: 1
+    to:
+      - add: {neighbors: [0], node: {name: leaf, values: {}}}
+"#,
+        )
+        .unwrap();
+        let first = grow(&rule, 7, 6);
+        assert!(first.order() > 1);
+        assert_eq!(serialized(&first), serialized(&grow(&rule, 7, 6)));
+    }
+
+    #[test]
+    fn test_probability_zero() {
+        let rule: Rule = serde_yaml::from_str(
+            r#"
+from:
+  nodes: [{id: 0}]
+probability: 0
+to:
+  - delete: 0
+"#,
+        )
+        .unwrap();
+        assert_eq!(grow(&rule, 0, 3).order(), 1);
+    }
+
+    #[test]
+    fn test_alternative_weights() {
+        // An alternative with no weight is never picked
+        let rule: Rule = serde_yaml::from_str(
+            r#"
+from:
+  nodes: [{id: 0}]
+alternatives:
+  - weight: 0
+    to:
+      - delete: 0
+  - weight: 1
+    to: []
+"#,
+        )
+        .unwrap();
+        for seed in 0..10 {
+            assert_eq!(grow(&rule, seed, 3).order(), 1);
+        }
+    }
+
+    #[test]
+    fn test_guard() {
+        use crate::rgg::{Node, Value};
+        use gamma::graph::AppendableGraph;
+
+        let rule: Rule = serde_yaml::from_str(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}, {id: 1, name: stem}]
+  edges: [[0, 1]]
+when: "n0.len > n1.len && (n0.len + n1.len) >= 3"
+to: []
+"#,
+        )
+        .unwrap();
+        let mut graph = RggGraph::new();
+        let mut ids = vec![];
+        for len in &[1, 2, 3] {
+            let mut node = Node::new("stem");
+            node.values.insert("len".to_string(), Value::Int(*len));
+            ids.push(graph.insert_node_with(node));
+        }
+        graph.graph.add_edge(ids[0], ids[1]).unwrap();
+        graph.graph.add_edge(ids[1], ids[2]).unwrap();
+        graph.graph.advance_generation();
+
+        let matches = rule
+            .guarded_matches(&graph, &Environment::default())
+            .unwrap();
+        assert_eq!(matches.len(), 2);
+        assert!(matches.iter().all(|m| m[&0] > m[&1]));
+
+        // Guards that can't be evaluated fail the rule
+        let rule = Rule {
+            when: Some("n0.height > 1".to_string()),
+            ..rule
+        };
+        assert!(matches!(
+            rule.guarded_matches(&graph, &Environment::default()),
+            Err(RggError::Expr(_))
+        ));
+    }
+
+    #[test]
+    fn test_failed_match_is_rolled_back() {
+        let rule: Rule = serde_yaml::from_str(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}]
+to:
+  - replace: {target: 0, with: {name: leaf, values: {}}}
+  - add: {neighbors: [0], node: {name: stem, values: {len: "n0.missing + 1"}}}
+"#,
+        )
+        .unwrap();
+        let mut graph = RggGraph::new();
+        let stem = graph.insert_node_with(crate::rgg::Node::new("stem"));
+        graph.graph.advance_generation();
+        let before = serialized(&graph);
+
+        let result = rule
+            .apply(
+                &mut graph,
+                &mut StdRng::seed_from_u64(0),
+                &Environment::default(),
+            )
+            .unwrap();
+        assert!(result.is_empty());
+        assert_eq!(result.failed.len(), 1);
+        let failure = &result.failed[0];
+        assert_eq!(failure.mapping, maplit::hashmap! {0 => stem});
+        assert_eq!(failure.procedure, 1);
+        assert!(matches!(failure.error, RggError::Expr(_)));
+        let stats = &result.stats[0];
+        assert_eq!((stats.matches, stats.applied, stats.failed), (1, 0, 1));
+        assert_eq!(serialized(&graph), before);
+        assert!(!graph.graph.node_is_dirty(stem));
+    }
+
+    #[test]
+    fn test_vanished_targets_are_skipped() {
+        use gamma::graph::AppendableGraph;
+
+        let rule = Rule::from_yaml(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}, {id: 1, name: stem}]
+  edges: [[0, 1]]
+to:
+  - delete: 1
+"#,
+        )
+        .unwrap();
+        let mut graph = RggGraph::new();
+        let a = graph.insert_node_with(crate::rgg::Node::new("stem"));
+        let b = graph.insert_node_with(crate::rgg::Node::new("stem"));
+        graph.graph.add_edge(a, b).unwrap();
+        graph.graph.advance_generation();
+
+        // Whichever node is deleted first, the other match refers to it
+        let result = rule
+            .apply(
+                &mut graph,
+                &mut StdRng::seed_from_u64(0),
+                &Environment::default(),
+            )
+            .unwrap();
+        assert_eq!(graph.order(), 1);
+        assert!(result.failed.is_empty());
+        assert_eq!(
+            result.stats,
+            vec![RuleStats {
+                matches: 2,
+                skipped: 1,
+                applied: 1,
+                failed: 0,
+                ..result.stats[0].clone()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_dirty_match_is_skipped_as_a_whole() {
+        use gamma::graph::AppendableGraph;
+
+        let rule = Rule::from_yaml(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}, {id: 1, name: stem}]
+  edges: [[0, 1]]
+to:
+  - replace: {target: 0, with: {name: stem, values: {}}}
+  - replace: {target: 1, with: {name: stem, values: {}}}
+  - add: {neighbors: [0], node: {name: leaf, values: {}}}
+"#,
+        )
+        .unwrap();
+        let mut graph = RggGraph::new();
+        let a = graph.insert_node_with(crate::rgg::Node::new("stem"));
+        let b = graph.insert_node_with(crate::rgg::Node::new("stem"));
+        graph.graph.add_edge(a, b).unwrap();
+        graph.graph.advance_generation();
+
+        // The mirrored match would only add a leaf, since both stems were replaced already
+        let result = rule
+            .apply(
+                &mut graph,
+                &mut StdRng::seed_from_u64(0),
+                &Environment::default(),
+            )
+            .unwrap();
+        assert_eq!(result.added.len(), 1);
+        assert_eq!(result.modified.len(), 2);
+        assert_eq!(graph.order(), 3);
+        assert_eq!((result.stats[0].matches, result.stats[0].skipped), (2, 1));
+    }
+
+    #[test]
+    fn test_applied_counts_changes() {
+        // The node is dirty by the time it is deleted, but only because of the match itself
+        let rule = Rule::from_yaml(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}]
+to:
+  - replace: {target: 0, with: {name: leaf, values: {}}}
+  - delete: 0
+"#,
+        )
+        .unwrap();
+        let mut graph = RggGraph::new();
+        graph.insert_node_with(crate::rgg::Node::new("stem"));
+        graph.graph.advance_generation();
+        let result = rule
+            .apply(
+                &mut graph,
+                &mut StdRng::seed_from_u64(0),
+                &Environment::default(),
+            )
+            .unwrap();
+        assert_eq!(graph.order(), 0);
+        assert_eq!((result.stats[0].matches, result.stats[0].applied), (1, 2));
+        assert_eq!(result.applied[0].modified, vec![0]);
+        assert_eq!(result.applied[0].removed, vec![0]);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let rule = Rule::from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}]
+  not:
+    - nodes: [{id: 1}]
+      edges: [[0, 2]]
+to: []
+"#,
+        )
+        .unwrap();
+        let mut graph = RggGraph::new();
+        graph.insert_node();
+        let result = rule.apply(
+            &mut graph,
+            &mut StdRng::seed_from_u64(0),
+            &Environment::default(),
+        );
+        assert!(matches!(result, Err(RggError::Invalid(_))));
+
+        // Conditions on parameters that don't exist
+        let rule = Rule::from_yaml(
+            r#"
+from:
+  nodes: [{id: 0}]
+  not:
+    - nodes: [{id: 1, values: {len: [gt, {param: min_len}]}}]
+      edges: [[0, 1]]
+to: []
+"#,
+        )
+        .unwrap();
+        let result = rule.apply(
+            &mut graph,
+            &mut StdRng::seed_from_u64(0),
+            &Environment::default(),
+        );
+        assert!(
+            matches!(&result, Err(RggError::Invalid(message)) if message.contains("min_len")),
+            "{:?}",
+            result.err()
+        );
+        assert!(matches!(
+            Rule::from_yaml("from: {nodes: [{id: x}]}"),
+            Err(RggError::Parse(_))
+        ));
     }
 }
@@ -1,12 +1,17 @@
+use crate::rgg::condition::{Bounds, Operand, StringPattern};
 use crate::rgg::procedures::*;
+use crate::rgg::value::RGGType;
 use crate::rgg::Condition;
-use crate::rgg::Value;
+use crate::rgg::{EdgeLabel, Neighbor, RggGraph, Value, ValueExpr};
 use core::fmt::Formatter;
-use serde::de::{Error, SeqAccess, Unexpected, Visitor};
-use serde::{Deserialize, Deserializer};
-
-#[derive(Deserialize)]
-struct RawValue(String);
+use gamma::graph::Graph;
+use regex::Regex;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Error, IgnoredAny, MapAccess, SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 
 #[derive(Deserialize)]
 struct I32(i32);
@@ -19,16 +24,309 @@ impl<'de> Deserialize<'de> for Value {
     where
         D: Deserializer<'de>,
     {
-        let s = RawValue::deserialize(deserializer)?.0;
-        match s.parse::<i32>() {
-            Ok(i) => Ok(Value::from(i)),
-            Err(_) => {
-                let f = s
-                    .parse::<f32>()
-                    .map_err(|_| D::Error::invalid_type(Unexpected::Str(&s), &"float or int"))?;
-                Ok(Value::from(f))
-            }
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("an int, float, bool, string or a sequence of three floats")
+    }
+
+    fn visit_bool<E: Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
+        i32::try_from(v)
+            .map(Value::Int)
+            .map_err(|_| E::invalid_value(Unexpected::Signed(v), &"a 32 bit int"))
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+        i32::try_from(v)
+            .map(Value::Int)
+            .map_err(|_| E::invalid_value(Unexpected::Unsigned(v), &"a 32 bit int"))
+    }
+
+    fn visit_f64<E: Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Value::Float(v as f32))
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, <A as SeqAccess<'de>>::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut v = [0f32; 3];
+        for (i, component) in v.iter_mut().enumerate() {
+            *component = seq
+                .next_element()?
+                .ok_or_else(|| Error::invalid_length(i, &self))?;
+        }
+        if seq.next_element::<f32>()?.is_some() {
+            return Err(Error::invalid_length(4, &self));
+        }
+        Ok(Value::Vec3(v))
+    }
+}
+
+impl<'de> Deserialize<'de> for Operand {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(OperandVisitor)
+    }
+}
+
+/// Reads an Operand: either a value, or a single-entry map like `{param: min_len}`.
+struct OperandVisitor;
+
+impl<'de> Visitor<'de> for OperandVisitor {
+    type Value = Operand;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a value or a map with a single param entry")
+    }
+
+    fn visit_bool<E: Error>(self, v: bool) -> Result<Self::Value, E> {
+        ValueVisitor.visit_bool(v).map(Operand::Value)
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
+        ValueVisitor.visit_i64(v).map(Operand::Value)
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+        ValueVisitor.visit_u64(v).map(Operand::Value)
+    }
+
+    fn visit_f64<E: Error>(self, v: f64) -> Result<Self::Value, E> {
+        ValueVisitor.visit_f64(v).map(Operand::Value)
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        ValueVisitor.visit_str(v).map(Operand::Value)
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, <A as SeqAccess<'de>>::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        ValueVisitor.visit_seq(seq).map(Operand::Value)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, <A as MapAccess<'de>>::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let (key, name): (String, String) = map
+            .next_entry()?
+            .ok_or_else(|| Error::invalid_length(0, &self))?;
+        if key != "param" {
+            return Err(Error::unknown_field(&key, &["param"]));
+        }
+        if map.next_key::<String>()?.is_some() {
+            return Err(Error::invalid_length(2, &self));
+        }
+        Ok(Operand::Param(name))
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueExpr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueExprVisitor)
+    }
+}
+
+/// Reads a ValueExpr. Strings are expressions, other values are literals,
+/// and a single-entry map like `{int: "x + 1"}` declares the type of its contents.
+struct ValueExprVisitor;
+
+impl ValueExprVisitor {
+    fn typed<E: Error>(designator: &str, value: Value) -> Result<ValueExpr, E> {
+        let rgg_type = match designator {
+            "int" => RGGType::Int,
+            "float" => RGGType::Float,
+            "bool" => RGGType::Bool,
+            "string" => RGGType::String,
+            e => return Err(E::unknown_variant(e, &["int", "float", "bool", "string"])),
+        };
+        match (rgg_type, value) {
+            (RGGType::String, Value::String(s)) => Ok(ValueExpr::Literal(Value::String(s))),
+            (rgg_type, Value::String(expr)) => Ok(ValueExpr::Expr {
+                expr,
+                rgg_type: Some(rgg_type),
+            }),
+            (RGGType::Int, Value::Int(i)) => Ok(ValueExpr::Literal(Value::Int(i))),
+            (RGGType::Float, Value::Int(i)) => Ok(ValueExpr::Literal(Value::Float(i as f32))),
+            (RGGType::Float, Value::Float(f)) => Ok(ValueExpr::Literal(Value::Float(f))),
+            (RGGType::Bool, Value::Bool(b)) => Ok(ValueExpr::Literal(Value::Bool(b))),
+            (_, value) => Err(E::custom(format!(
+                "{:?} is not a valid {} value",
+                value, designator
+            ))),
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for ValueExprVisitor {
+    type Value = ValueExpr;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a value, an expression, or a map of a type to an expression")
+    }
+
+    fn visit_bool<E: Error>(self, v: bool) -> Result<Self::Value, E> {
+        ValueVisitor.visit_bool(v).map(ValueExpr::Literal)
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
+        ValueVisitor.visit_i64(v).map(ValueExpr::Literal)
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+        ValueVisitor.visit_u64(v).map(ValueExpr::Literal)
+    }
+
+    fn visit_f64<E: Error>(self, v: f64) -> Result<Self::Value, E> {
+        ValueVisitor.visit_f64(v).map(ValueExpr::Literal)
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(ValueExpr::from(v))
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, <A as SeqAccess<'de>>::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        ValueVisitor.visit_seq(seq).map(ValueExpr::Literal)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, <A as MapAccess<'de>>::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let (designator, value): (String, Value) = map
+            .next_entry()?
+            .ok_or_else(|| Error::invalid_length(0, &self))?;
+        if map.next_key::<String>()?.is_some() {
+            return Err(Error::invalid_length(2, &self));
         }
+        Self::typed(&designator, value)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Value::Int(i) => serializer.serialize_i32(*i),
+            Value::Float(f) => serializer.serialize_f32(*f),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Vec3(v) => v.serialize(serializer),
+        }
+    }
+}
+
+/// A node as it is written out, in the same layout as an axiom node of a plant definition.
+#[derive(Serialize)]
+struct SerializedNode<'a> {
+    id: usize,
+    name: &'a str,
+    values: &'a BTreeMap<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+}
+
+/// An edge as it is written out: `[from, to]` for plain edges, `[from, to, label]` otherwise,
+/// and `[from, to, label, values]` for edges with values.
+struct SerializedEdge<'a> {
+    from: usize,
+    to: usize,
+    label: EdgeLabel,
+    values: Option<&'a BTreeMap<String, Value>>,
+}
+
+impl Serialize for SerializedEdge<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let len = match (self.values, self.label) {
+            (Some(_), _) => 4,
+            (None, EdgeLabel::Plain) => 2,
+            (None, _) => 3,
+        };
+        let mut tuple = serializer.serialize_tuple(len)?;
+        tuple.serialize_element(&self.from)?;
+        tuple.serialize_element(&self.to)?;
+        if len > 2 {
+            tuple.serialize_element(&self.label)?;
+        }
+        if let Some(values) = self.values {
+            tuple.serialize_element(values)?;
+        }
+        tuple.end()
+    }
+}
+
+#[derive(Serialize)]
+struct SerializedGraph<'a> {
+    nodes: Vec<SerializedNode<'a>>,
+    edges: Vec<SerializedEdge<'a>>,
+}
+
+impl Serialize for RggGraph {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let nodes = self
+            .graph
+            .nodes()
+            .filter_map(|id| {
+                self.values.get(id).map(|node| SerializedNode {
+                    id: *id,
+                    name: node.name.as_str(),
+                    values: &node.values,
+                    parent: self.graph.get_ancestor(*id),
+                })
+            })
+            .collect();
+        let edges = self
+            .graph
+            .edges()
+            .map(|(a, b)| {
+                let label = self.graph.edge_label(*a, *b).unwrap_or_default();
+                let (from, to) = match self.graph.edge_source(*a, *b) {
+                    Some(source) if source == *b => (*b, *a),
+                    _ => (*a, *b),
+                };
+                SerializedEdge {
+                    from,
+                    to,
+                    label,
+                    values: self.graph.edge_values(*a, *b),
+                }
+            })
+            .collect();
+        SerializedGraph { nodes, edges }.serialize(serializer)
     }
 }
 
@@ -42,6 +340,82 @@ impl<'de> Deserialize<'de> for DeleteProcedure {
     }
 }
 
+impl<'de> Deserialize<'de> for Neighbor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(NeighborVisitor)
+    }
+}
+
+/// The map form of a Neighbor, which is the only one that can give the new edge values.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NeighborMap {
+    id: i32,
+    #[serde(default)]
+    label: EdgeLabel,
+    #[serde(default)]
+    values: BTreeMap<String, ValueExpr>,
+}
+
+/// Reads a Neighbor: a rule node id, a sequence of an id and an edge label,
+/// or a map with an id and optionally a label and values.
+struct NeighborVisitor;
+
+impl<'de> Visitor<'de> for NeighborVisitor {
+    type Value = Neighbor;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a node id, a sequence of a node id and an edge label, or a map")
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
+        i32::try_from(v)
+            .map(Neighbor::from)
+            .map_err(|_| E::invalid_value(Unexpected::Signed(v), &"a 32 bit int"))
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+        i32::try_from(v)
+            .map(Neighbor::from)
+            .map_err(|_| E::invalid_value(Unexpected::Unsigned(v), &"a 32 bit int"))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, <A as SeqAccess<'de>>::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let id = seq
+            .next_element()?
+            .ok_or_else(|| Error::invalid_length(0, &self))?;
+        let label = seq
+            .next_element()?
+            .ok_or_else(|| Error::invalid_length(1, &self))?;
+        if seq.next_element::<IgnoredAny>()?.is_some() {
+            return Err(Error::invalid_length(3, &self));
+        }
+        Ok(Neighbor {
+            id,
+            label,
+            values: Default::default(),
+        })
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, <A as MapAccess<'de>>::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let neighbor = NeighborMap::deserialize(MapAccessDeserializer::new(map))?;
+        Ok(Neighbor {
+            id: neighbor.id,
+            label: neighbor.label,
+            values: neighbor.values,
+        })
+    }
+}
+
 impl<'de> Deserialize<'de> for MergeProcedure {
     fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
     where
@@ -69,18 +443,36 @@ impl<'de> Deserialize<'de> for Condition {
     }
 }
 
+/// Reads a Condition: a designator followed by its arguments, such as `[lt, 3]`,
+/// `[range, 0, 1, "[]"]`, `[in, [1, 2]]`, `[exists]` or `[and, [gt, 0], [lt, 1]]`.
 struct ConditionVisitor;
 
+const DESIGNATORS: &[&str] = &[
+    "eq", "ne", "lt", "gt", "lte", "gte", "range", "in", "glob", "regex", "exists", "missing",
+    "and", "or", "not",
+];
+
 impl ConditionVisitor {
-    fn parse_condition(designator: String, value: Value) -> Condition {
-        match designator.as_str() {
-            "eq" => Condition::Equals(value),
-            "lt" => Condition::LessThan(value),
-            "gt" => Condition::GreaterThan(value),
-            "lte" => Condition::LessThanOrEquals(value),
-            "gte" => Condition::GreaterThanOrEquals(value),
-            e => panic!("Invalid designator {:?} should have been caught earlier", e),
-        }
+    /// The conditions that compare against a single operand.
+    fn comparison(designator: &str) -> Option<fn(Operand) -> Condition> {
+        Some(match designator {
+            "eq" => Condition::Equals,
+            "ne" => Condition::NotEquals,
+            "lt" => Condition::LessThan,
+            "gt" => Condition::GreaterThan,
+            "lte" => Condition::LessThanOrEquals,
+            "gte" => Condition::GreaterThanOrEquals,
+            _ => return None,
+        })
+    }
+
+    fn required<'de, A, T>(seq: &mut A, designator: &str, what: &str) -> Result<T, A::Error>
+    where
+        A: SeqAccess<'de>,
+        T: Deserialize<'de>,
+    {
+        seq.next_element()?
+            .ok_or_else(|| A::Error::custom(format!("Missing {} for {}", what, designator)))
     }
 }
 
@@ -89,7 +481,7 @@ impl<'de> Visitor<'de> for ConditionVisitor {
 
     fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
         formatter.write_str(
-            "a sequence of a string followed by one or two values, representing a Condition",
+            "a sequence of a string followed by the condition's values, representing a Condition",
         )
     }
 
@@ -97,29 +489,105 @@ impl<'de> Visitor<'de> for ConditionVisitor {
     where
         A: SeqAccess<'de>,
     {
-        let designation: String = seq
+        let designator: String = seq
             .next_element()?
             .ok_or_else(|| <A as SeqAccess<'de>>::Error::custom("Missing designator"))?;
-        match designation.as_str() {
-            "eq" | "lt" | "gt" | "lte" | "gte" => {
-                let value: Value = seq
-                    .next_element()?
-                    .ok_or_else(|| <A as SeqAccess<'de>>::Error::custom("Missing value"))?;
-                Ok(Self::parse_condition(designation, value))
+        let d = designator.as_str();
+        let condition = if let Some(comparison) = Self::comparison(d) {
+            comparison(Self::required(&mut seq, d, "value")?)
+        } else {
+            match d {
+                "range" => {
+                    let low = Self::required(&mut seq, d, "lower value")?;
+                    let high = Self::required(&mut seq, d, "upper value")?;
+                    let bounds = match seq.next_element::<String>()? {
+                        Some(bounds) => Bounds::parse(&bounds).ok_or_else(|| {
+                            Error::invalid_value(
+                                Unexpected::Str(&bounds),
+                                &"one of \"[]\", \"[)\", \"(]\" or \"()\"",
+                            )
+                        })?,
+                        None => Bounds::default(),
+                    };
+                    Condition::Range(low, high, bounds)
+                }
+                "in" => Condition::In(Self::required(&mut seq, d, "list of values")?),
+                "glob" => {
+                    Condition::Matches(StringPattern::Glob(Self::required(&mut seq, d, "pattern")?))
+                }
+                "regex" => {
+                    let pattern: String = Self::required(&mut seq, d, "pattern")?;
+                    Condition::Matches(StringPattern::Regex(regex(&pattern)?))
+                }
+                "exists" => Condition::Exists,
+                "missing" => Condition::Missing,
+                "and" | "or" => {
+                    let mut conditions = vec![];
+                    while let Some(condition) = seq.next_element()? {
+                        conditions.push(condition);
+                    }
+                    if conditions.is_empty() {
+                        return Err(Error::custom(format!("{} needs at least one condition", d)));
+                    }
+                    return Ok(if d == "and" {
+                        Condition::And(conditions)
+                    } else {
+                        Condition::Or(conditions)
+                    });
+                }
+                "not" => Condition::Not(Box::new(Self::required(&mut seq, d, "condition")?)),
+                e => return Err(Error::unknown_variant(e, DESIGNATORS)),
             }
-            "range" => {
-                let range_low: Value = seq.next_element()?.ok_or_else(|| {
-                    <A as SeqAccess<'de>>::Error::custom("Missing value for range lower")
-                })?;
-                let range_high: Value = seq.next_element()?.ok_or_else(|| {
-                    <A as SeqAccess<'de>>::Error::custom("Missing value for range upper")
-                })?;
-                Ok(Condition::Range(range_low, range_high))
-            }
-            e => Err(<A as SeqAccess<'de>>::Error::custom(format!(
-                "Unknown designator {:?}",
-                e
-            ))),
+        };
+        if seq.next_element::<IgnoredAny>()?.is_some() {
+            return Err(Error::custom(format!("Too many values for {}", d)));
+        }
+        Ok(condition)
+    }
+}
+
+fn regex<E: Error>(pattern: &str) -> Result<Regex, E> {
+    Regex::new(pattern).map_err(|e| E::custom(format!("Invalid regex {:?}: {}", pattern, e)))
+}
+
+impl<'de> Deserialize<'de> for StringPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(StringPatternVisitor)
+    }
+}
+
+/// Reads a StringPattern: a plain string matches exactly, and a single-entry map like
+/// `{glob: "st*"}` or `{regex: "^st"}` matches by pattern.
+struct StringPatternVisitor;
+
+impl<'de> Visitor<'de> for StringPatternVisitor {
+    type Value = StringPattern;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a string or a map with a single glob or regex entry")
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(StringPattern::Exact(v.to_string()))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, <A as MapAccess<'de>>::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let (key, pattern): (String, String) = map
+            .next_entry()?
+            .ok_or_else(|| Error::invalid_length(0, &self))?;
+        if map.next_key::<String>()?.is_some() {
+            return Err(Error::invalid_length(2, &self));
+        }
+        match key.as_str() {
+            "glob" => Ok(StringPattern::Glob(pattern)),
+            "regex" => Ok(StringPattern::Regex(regex(&pattern)?)),
+            e => Err(Error::unknown_field(e, &["glob", "regex"])),
         }
     }
 }
@@ -127,19 +595,93 @@ impl<'de> Visitor<'de> for ConditionVisitor {
 #[cfg(test)]
 mod test {
     use super::super::procedures::*;
+    use crate::rgg::condition::{Bounds, Operand, StringPattern};
+    use crate::rgg::value::RGGType;
     use crate::rgg::Condition;
-    use crate::rgg::{FromNode, Node, ToNode, Value};
+    use crate::rgg::{EdgeLabel, FromNode, Neighbor, Node, RggGraph, ToNode, Value, ValueExpr};
+    use gamma::graph::AppendableGraph;
 
     #[test]
     fn test_de_value_float() {
         let val: Value = serde_yaml::from_str("0.3").unwrap();
-        assert_eq!(val.get::<f32>(), 0.3);
+        assert_eq!(val, Value::Float(0.3));
     }
 
     #[test]
     fn test_de_value_int() {
         let val: Value = serde_yaml::from_str("3").unwrap();
-        assert_eq!(val.get::<i32>(), 3);
+        assert_eq!(val, Value::Int(3));
+    }
+
+    #[test]
+    fn test_de_value_other() {
+        let values: Vec<Value> =
+            serde_yaml::from_str("[true, hello, \"3\", [1, 2.5, -3]]").unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::Bool(true),
+                Value::from("hello"),
+                Value::from("3"),
+                Value::Vec3([1.0, 2.5, -3.0]),
+            ]
+        );
+        assert!(serde_yaml::from_str::<Value>("[1, 2]").is_err());
+        assert!(serde_yaml::from_str::<Value>("[1, 2, 3, 4]").is_err());
+        assert!(serde_yaml::from_str::<Value>("3000000000").is_err());
+    }
+
+    #[test]
+    fn test_ser_value() {
+        let values = vec![
+            Value::new_int(3),
+            Value::new_float(0.5),
+            Value::Bool(false),
+            Value::from("hi"),
+            Value::Vec3([1.0, 2.0, 3.0]),
+        ];
+        assert_eq!(
+            serde_json::to_string(&values).unwrap(),
+            r#"[3,0.5,false,"hi",[1.0,2.0,3.0]]"#
+        );
+    }
+
+    #[test]
+    fn test_ser_graph() {
+        let mut graph = RggGraph::new();
+        graph.insert_node_with(Node::new("stem"));
+        graph.insert_node_with(Node::new("shoot"));
+        graph.graph.add_edge(0, 1).unwrap();
+        graph.graph.add_ancestor(1, 0);
+        let json = serde_json::to_string(&graph).unwrap();
+        assert_eq!(
+            json,
+            r#"{"nodes":[{"id":0,"name":"stem","values":{}},{"id":1,"name":"shoot","values":{},"parent":0}],"edges":[[0,1]]}"#
+        );
+
+        graph.insert_node_with(Node::new("leaf"));
+        graph
+            .graph
+            .add_labeled_edge(2, 1, EdgeLabel::Branch)
+            .unwrap();
+        let json = serde_json::to_string(&graph).unwrap();
+        assert!(
+            json.ends_with(r#""edges":[[0,1],[2,1,"branch"]]}"#),
+            "{}",
+            json
+        );
+
+        graph.graph.set_edge_values(
+            0,
+            1,
+            maplit::btreemap! {"angle".to_string() => Value::Float(0.5)},
+        );
+        let json = serde_json::to_string(&graph).unwrap();
+        assert!(
+            json.ends_with(r#""edges":[[0,1,"plain",{"angle":0.5}],[2,1,"branch"]]}"#),
+            "{}",
+            json
+        );
     }
 
     #[test]
@@ -154,8 +696,8 @@ values:
         .unwrap();
 
         assert_eq!(node.name, "hi".to_string());
-        assert_eq!(node.values["foo"].get::<i32>(), 1);
-        assert_eq!(node.values["bar"].get::<f32>(), 2.0);
+        assert_eq!(node.values["foo"], Value::Int(1));
+        assert_eq!(node.values["bar"], Value::Float(2.0));
     }
 
     #[test]
@@ -183,14 +725,51 @@ add:
     values:
       foo: 1
       bar: 2.0
-  neighbors: [1, 2, 3, 4]"#,
+  neighbors:
+    - 1
+    - 2
+    - [3, branch]
+    - [4, "<"]
+    - {id: 5, label: branch, values: {angle: 30.0}}
+    - {id: 6}"#,
         )
         .unwrap();
         match &proc {
             Procedure::Add(proc) => {
                 assert_eq!(proc.new_node.name, "hi");
-                assert_eq!(proc.new_node.values["foo"].get::<i32>(), 1);
-                assert_eq!(proc.neighbors, vec![1, 2, 3, 4]);
+                assert_eq!(
+                    proc.new_node.values["foo"],
+                    ValueExpr::Literal(Value::Int(1))
+                );
+                assert_eq!(
+                    proc.neighbors,
+                    vec![
+                        1.into(),
+                        2.into(),
+                        Neighbor {
+                            id: 3,
+                            label: EdgeLabel::Branch,
+                            values: Default::default(),
+                        },
+                        Neighbor {
+                            id: 4,
+                            label: EdgeLabel::Successor,
+                            values: Default::default(),
+                        },
+                        Neighbor {
+                            id: 5,
+                            label: EdgeLabel::Branch,
+                            values: maplit::btreemap! {
+                                "angle".to_string() => ValueExpr::Literal(Value::Float(30.0))
+                            },
+                        },
+                        Neighbor {
+                            id: 6,
+                            label: EdgeLabel::Plain,
+                            values: Default::default(),
+                        }
+                    ]
+                );
             }
             _ => panic!("Invalid procedure: {:?}", proc),
         }
@@ -202,7 +781,7 @@ add:
             r#"
 replace:
   target: 0
-  replace:
+  with:
     name: "hi"
     values:
       foo: 5.5
@@ -212,8 +791,14 @@ replace:
         match &proc {
             Procedure::Replace(proc) => {
                 assert_eq!(proc.replacement.name, "hi");
-                assert_eq!(proc.replacement.values["foo"].get::<f32>(), 5.5);
-                assert_eq!(proc.replacement.values["bar"].get::<i32>(), 2);
+                assert_eq!(
+                    proc.replacement.values["foo"],
+                    ValueExpr::Literal(Value::Float(5.5))
+                );
+                assert_eq!(
+                    proc.replacement.values["bar"],
+                    ValueExpr::Literal(Value::Int(2))
+                );
                 assert_eq!(proc.target, 0);
             }
             _ => panic!("Invalid procedure: {:?}", proc),
@@ -232,6 +817,38 @@ replace:
         }
     }
 
+    #[test]
+    fn test_de_edge_procedures() {
+        let procs: Vec<Procedure> = serde_yaml::from_str(
+            r#"
+- set_edge: {from: 0, to: 1, values: {angle: "angle + 5"}}
+- connect: [0, 2]
+- connect: {from: 0, to: 2, label: branch, values: {angle: 30}}
+- disconnect: [1, 2]
+- split: {from: 0, to: 1, node: {name: internode, values: {}}, label: successor}
+- move_edge: {edge: [0, 1], to: [2, 1]}"#,
+        )
+        .unwrap();
+        match &procs[..] {
+            [Procedure::SetEdge(set), Procedure::Connect(plain), Procedure::Connect(branch), Procedure::Disconnect(disconnect), Procedure::Split(split), Procedure::MoveEdge(move_edge)] =>
+            {
+                assert_eq!((set.from, set.to, set.label), (0, 1, None));
+                assert_eq!(set.values["angle"], "angle + 5".into());
+                assert_eq!((plain.to, plain.label), (2, EdgeLabel::Plain));
+                assert!(plain.values.is_empty());
+                assert_eq!(branch.label, EdgeLabel::Branch);
+                assert_eq!(branch.values["angle"], ValueExpr::Literal(Value::Int(30)));
+                assert_eq!((disconnect.from, disconnect.to), (1, 2));
+                assert_eq!(split.new_node.name, "internode");
+                assert_eq!(split.label, Some(EdgeLabel::Successor));
+                assert_eq!((move_edge.edge, move_edge.to), ((0, 1), (2, 1)));
+            }
+            _ => panic!("Invalid procedures: {:?}", procs),
+        }
+        assert!(serde_yaml::from_str::<Procedure>("split: {from: 0, to: 1}").is_err());
+        assert!(serde_yaml::from_str::<Procedure>("move_edge: {edge: [0, 1], to: [2]}").is_err());
+    }
+
     #[test]
     fn test_de_conditions() {
         let conditions: Vec<Condition> = serde_yaml::from_str(
@@ -241,24 +858,118 @@ replace:
 - [gt, 3.0]
 - [lte, 10]
 - [gte, -3]
-- [range, 0, 2]"#,
+- [range, 0, 2]
+- [range, {param: min_len}, 2]"#,
         )
         .unwrap();
-        assert_eq!(conditions[0], Condition::Equals(Value::new_int(3)));
-        assert_eq!(conditions[1], Condition::LessThan(Value::new_float(2.0)));
-        assert_eq!(conditions[2], Condition::GreaterThan(Value::new_float(3.0)));
+        assert_eq!(conditions[0], Condition::Equals(Value::new_int(3).into()));
+        assert_eq!(
+            conditions[1],
+            Condition::LessThan(Value::new_float(2.0).into())
+        );
+        assert_eq!(
+            conditions[2],
+            Condition::GreaterThan(Value::new_float(3.0).into())
+        );
         assert_eq!(
             conditions[3],
-            Condition::LessThanOrEquals(Value::new_int(10))
+            Condition::LessThanOrEquals(Value::new_int(10).into())
         );
         assert_eq!(
             conditions[4],
-            Condition::GreaterThanOrEquals(Value::new_int(-3))
+            Condition::GreaterThanOrEquals(Value::new_int(-3).into())
         );
         assert_eq!(
             conditions[5],
-            Condition::Range(Value::new_int(0), Value::new_int(2))
+            Condition::Range(
+                Value::new_int(0).into(),
+                Value::new_int(2).into(),
+                Bounds::default()
+            )
+        );
+        assert_eq!(
+            conditions[6],
+            Condition::Range(
+                Operand::Param("min_len".to_string()),
+                Value::new_int(2).into(),
+                Bounds::default()
+            )
         );
+        assert!(serde_yaml::from_str::<Condition>("[lt, {parameter: x}]").is_err());
+        // Ranges without bounds include their upper end, as they did before bounds existed
+        let parameters = maplit::btreemap! {"min_len".to_string() => 0.0};
+        assert!(conditions[5].check(Some(&Value::new_int(2)), &parameters));
+        assert!(conditions[6].check(Some(&Value::new_float(2.0)), &parameters));
+    }
+
+    #[test]
+    fn test_de_more_conditions() {
+        let conditions: Vec<Condition> = serde_yaml::from_str(
+            r#"
+- [ne, 3]
+- [in, [1, leaf, {param: x}]]
+- [range, 0, 1, "(]"]
+- [exists]
+- [missing]
+- [glob, "st*"]
+- [regex, "^st"]
+- [and, [gt, 0], [not, [eq, 1]]]
+- [or, [missing]]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            conditions[0],
+            Condition::NotEquals(Value::new_int(3).into())
+        );
+        assert_eq!(
+            conditions[1],
+            Condition::In(vec![
+                Value::new_int(1).into(),
+                Value::from("leaf").into(),
+                Operand::Param("x".to_string())
+            ])
+        );
+        assert_eq!(
+            conditions[2],
+            Condition::Range(
+                Value::new_int(0).into(),
+                Value::new_int(1).into(),
+                Bounds::parse("(]").unwrap()
+            )
+        );
+        assert_eq!(conditions[3], Condition::Exists);
+        assert_eq!(conditions[4], Condition::Missing);
+        assert_eq!(
+            conditions[5],
+            Condition::Matches(StringPattern::Glob("st*".to_string()))
+        );
+        assert!(
+            matches!(&conditions[6], Condition::Matches(StringPattern::Regex(r)) if r.as_str() == "^st")
+        );
+        assert_eq!(
+            conditions[7],
+            Condition::And(vec![
+                Condition::GreaterThan(Value::new_int(0).into()),
+                Condition::Not(Box::new(Condition::Equals(Value::new_int(1).into())))
+            ])
+        );
+        assert_eq!(conditions[8], Condition::Or(vec![Condition::Missing]));
+    }
+
+    #[test]
+    fn test_de_condition_errors() {
+        let error = |yaml: &str| match serde_yaml::from_str::<Condition>(yaml) {
+            Ok(condition) => panic!("{} parsed as {:?}", yaml, condition),
+            Err(e) => e.to_string(),
+        };
+        assert!(error("[equals, 1]").contains("unknown variant `equals`"));
+        assert!(error("[lt]").contains("Missing value for lt"));
+        assert!(error("[eq, 1, 2]").contains("Too many values for eq"));
+        assert!(error("[range, 0]").contains("Missing upper value for range"));
+        assert!(error("[range, 0, 1, \"[[\"]").contains("invalid value"));
+        assert!(error("[regex, \"(\"]").contains("Invalid regex"));
+        assert!(error("[and]").contains("at least one condition"));
+        assert!(error("[exists, 1]").contains("Too many values"));
     }
 
     #[test]
@@ -278,12 +989,21 @@ values:
         )
         .unwrap();
         assert_eq!(node.id, 9);
-        assert_eq!(node.name, Some("Test Case".to_string()));
+        assert_eq!(node.name, Some("Test Case".into()));
         assert_eq!(node.values.len(), 1);
         assert_eq!(
             node.values["length"],
-            Condition::LessThan(Value::new_int(3))
+            Condition::LessThan(Value::new_int(3).into())
         );
+
+        let node: FromNode = serde_yaml::from_str("{id: 0, name: {glob: \"st*\"}}").unwrap();
+        let parameters = Default::default();
+        assert!(node.match_node(&Node::new("stem"), &parameters));
+        assert!(!node.match_node(&Node::new("leaf"), &parameters));
+        let node: FromNode = serde_yaml::from_str("{id: 0, name: {regex: \"af$\"}}").unwrap();
+        assert!(node.match_node(&Node::new("leaf"), &parameters));
+        assert!(!node.match_node(&Node::new("stem"), &parameters));
+        assert!(serde_yaml::from_str::<FromNode>("{id: 0, name: {like: st}}").is_err());
     }
 
     #[test]
@@ -297,6 +1017,40 @@ values:
         )
         .unwrap();
         assert_eq!(node.name, "Test Node");
-        assert_eq!(node.values["length"], "3 + 4 * x");
+        assert_eq!(node.values["length"], ValueExpr::from("3 + 4 * x"));
+    }
+
+    #[test]
+    fn test_de_value_expr() {
+        let values: Vec<ValueExpr> = serde_yaml::from_str(
+            r#"
+- {int: "sprouted + 1"}
+- {float: 3}
+- {bool: "x"}
+- {string: "leaf"}
+- [0, 0, 1]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            values[0],
+            ValueExpr::Expr {
+                expr: "sprouted + 1".to_string(),
+                rgg_type: Some(RGGType::Int)
+            }
+        );
+        assert_eq!(values[1], ValueExpr::Literal(Value::Float(3.0)));
+        assert_eq!(
+            values[2],
+            ValueExpr::Expr {
+                expr: "x".to_string(),
+                rgg_type: Some(RGGType::Bool)
+            }
+        );
+        assert_eq!(values[3], ValueExpr::Literal(Value::from("leaf")));
+        assert_eq!(values[4], ValueExpr::Literal(Value::Vec3([0.0, 0.0, 1.0])));
+
+        assert!(serde_yaml::from_str::<ValueExpr>("{int: 2.5}").is_err());
+        assert!(serde_yaml::from_str::<ValueExpr>("{vector: \"x\"}").is_err());
+        assert!(serde_yaml::from_str::<ValueExpr>("{int: \"x\", float: \"y\"}").is_err());
     }
 }
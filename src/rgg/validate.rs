@@ -0,0 +1,372 @@
+// Finding mistakes in rules without applying them, so they can be reported when a plant is loaded.
+use crate::rgg::condition::Condition;
+use crate::rgg::expr;
+use crate::rgg::procedures::Procedure;
+use crate::rgg::value::RGGType;
+use crate::rgg::{Environment, NodeSet, Rule, ValueExpr};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+/// A mistake in a rule that would make it fail or misbehave when applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleProblem {
+    /// Where the mistake is, as the keys and indices that lead to it in the rule's YAML,
+    /// e.g. `["to", "1", "merge"]`.
+    pub path: Vec<String>,
+    pub message: String,
+}
+
+impl RuleProblem {
+    fn new(path: &[String], message: String) -> Self {
+        Self {
+            path: path.to_vec(),
+            message,
+        }
+    }
+
+    /// Put the path of the rule itself in front, e.g. its index in a list of rules.
+    pub fn within(mut self, prefix: &[String]) -> Self {
+        self.path.splice(0..0, prefix.iter().cloned());
+        self
+    }
+}
+
+impl Display for RuleProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.join("."), self.message)
+    }
+}
+
+/// Extend a path by one key or index.
+fn join(path: &[String], key: impl ToString) -> Vec<String> {
+    let mut path = path.to_vec();
+    path.push(key.to_string());
+    path
+}
+
+/// What an expression may refer to.
+struct Scope<'a> {
+    /// The rule ids of the matched nodes.
+    pattern: &'a BTreeSet<i32>,
+    env: &'a Environment,
+    /// Whether a node's values are available by name, along with its degree and depth.
+    base_node: bool,
+    /// Whether values are available by name at all. They can't be checked, since they depend on the graph.
+    base_values: bool,
+}
+
+impl Scope<'_> {
+    fn check(&self, expr: &str, boolean: bool, path: &[String], problems: &mut Vec<RuleProblem>) {
+        if let Err(e) = expr::check_syntax(expr, boolean) {
+            problems.push(RuleProblem::new(
+                path,
+                format!("Invalid expression {:?}: {}", expr, e),
+            ));
+            return;
+        }
+        for name in expr::variable_names(expr) {
+            if let Some((rule_id, value)) = expr::parse_reference(&name) {
+                if !self.pattern.contains(&rule_id) {
+                    problems.push(RuleProblem::new(
+                        path,
+                        format!(
+                            "Expression {:?} refers to n{}.{}, but there is no node {} in the pattern",
+                            expr, rule_id, value, rule_id
+                        ),
+                    ));
+                }
+                continue;
+            }
+            let defined = self.base_values
+                || self.env.parameters.contains_key(&name)
+                || ["generation", "pi", "e"].contains(&name.as_str())
+                || (self.base_node && ["degree", "depth"].contains(&name.as_str()));
+            if !defined {
+                problems.push(RuleProblem::new(
+                    path,
+                    format!(
+                        "Expression {:?} refers to undefined variable {}",
+                        expr, name
+                    ),
+                ));
+            }
+        }
+    }
+
+    fn check_values(
+        &self,
+        values: &BTreeMap<String, ValueExpr>,
+        path: &[String],
+        problems: &mut Vec<RuleProblem>,
+    ) {
+        for (name, value) in values {
+            if let ValueExpr::Expr { expr, rgg_type } = value {
+                // Without a declared type the value may be a boolean, which allows more syntax
+                let boolean = !matches!(rgg_type, Some(RGGType::Int) | Some(RGGType::Float));
+                self.check(expr, boolean, &join(path, name), problems);
+            }
+        }
+    }
+}
+
+/// Check that the parameters a condition refers to exist.
+fn check_condition(
+    condition: &Condition,
+    env: &Environment,
+    path: &[String],
+    problems: &mut Vec<RuleProblem>,
+) {
+    for name in condition.parameters() {
+        if !env.parameters.contains_key(name) {
+            problems.push(RuleProblem::new(
+                path,
+                format!("Condition refers to undefined parameter {}", name),
+            ));
+        }
+    }
+}
+
+impl NodeSet {
+    /// Check for duplicate node ids, edges between unknown nodes and conditions on unknown
+    /// parameters. `outer` holds the ids of the enclosing patterns, which negative conditions
+    /// may refer to.
+    fn validate(
+        &self,
+        outer: &BTreeSet<i32>,
+        env: &Environment,
+        path: &[String],
+        problems: &mut Vec<RuleProblem>,
+    ) {
+        let mut known = outer.clone();
+        let mut ids = BTreeSet::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if !ids.insert(node.id) {
+                problems.push(RuleProblem::new(
+                    &join(&join(path, "nodes"), index),
+                    format!("Node id {} is used more than once", node.id),
+                ));
+            }
+            known.insert(node.id);
+            let node_path = join(&join(path, "nodes"), index);
+            for (name, condition) in &node.values {
+                check_condition(
+                    condition,
+                    env,
+                    &join(&join(&node_path, "values"), name),
+                    problems,
+                );
+            }
+        }
+        for (index, edge) in self.edges.iter().enumerate() {
+            for id in &[edge.from, edge.to] {
+                if !known.contains(id) {
+                    problems.push(RuleProblem::new(
+                        &join(&join(path, "edges"), index),
+                        format!(
+                            "Edge ({}, {}) refers to node {}, which is not in the pattern",
+                            edge.from, edge.to, id
+                        ),
+                    ));
+                }
+            }
+            let edge_path = join(&join(path, "edges"), index);
+            for (name, condition) in &edge.values {
+                check_condition(
+                    condition,
+                    env,
+                    &join(&join(&edge_path, "values"), name),
+                    problems,
+                );
+            }
+        }
+        for (index, forbidden) in self.not.iter().enumerate() {
+            forbidden.validate(&known, env, &join(&join(path, "not"), index), problems);
+        }
+    }
+}
+
+impl Procedure {
+    fn validate(&self, scope: &Scope, path: &[String], problems: &mut Vec<RuleProblem>) {
+        let path = join(path, self.name());
+        for target in self.targets() {
+            if !scope.pattern.contains(&target) {
+                problems.push(RuleProblem::new(
+                    &path,
+                    format!("Node {} is not in the pattern", target),
+                ));
+            }
+        }
+        let with_node = Scope {
+            base_node: true,
+            base_values: true,
+            ..*scope
+        };
+        match self {
+            Procedure::Replace(proc) => {
+                with_node.check_values(
+                    &proc.replacement.values,
+                    &join(&join(&path, "with"), "values"),
+                    problems,
+                );
+            }
+            Procedure::Add(proc) => {
+                // The first neighbor is the new node's ancestor
+                let node_scope = if proc.neighbors.is_empty() {
+                    scope
+                } else {
+                    &with_node
+                };
+                node_scope.check_values(
+                    &proc.new_node.values,
+                    &join(&join(&path, "node"), "values"),
+                    problems,
+                );
+                for (index, neighbor) in proc.neighbors.iter().enumerate() {
+                    let neighbor_path = join(&join(&path, "neighbors"), index);
+                    scope.check_values(&neighbor.values, &join(&neighbor_path, "values"), problems);
+                }
+            }
+            Procedure::Merge(proc) => {
+                if !proc.targets.contains(&proc.final_node) {
+                    problems.push(RuleProblem::new(
+                        &path,
+                        format!(
+                            "The final node {} is not one of the merged nodes",
+                            proc.final_node
+                        ),
+                    ));
+                }
+            }
+            Procedure::SetEdge(proc) => {
+                // The edge's current values are available by name
+                let edge_scope = Scope {
+                    base_values: true,
+                    ..*scope
+                };
+                edge_scope.check_values(&proc.values, &join(&path, "values"), problems);
+            }
+            Procedure::Connect(proc) => {
+                scope.check_values(&proc.values, &join(&path, "values"), problems);
+            }
+            Procedure::Split(proc) => {
+                with_node.check_values(
+                    &proc.new_node.values,
+                    &join(&join(&path, "node"), "values"),
+                    problems,
+                );
+            }
+            Procedure::Delete(_) | Procedure::Disconnect(_) | Procedure::MoveEdge(_) => {}
+        }
+    }
+}
+
+impl Rule {
+    /// Find mistakes in the rule without applying it. Expressions may refer to the parameters of `env`.
+    /// Only mistakes that are certain are reported: values of the graph's nodes aren't known
+    /// until the rule is applied, so references to them are only checked for matched nodes.
+    pub fn validate(&self, env: &Environment) -> Vec<RuleProblem> {
+        let mut problems = vec![];
+        let root = vec![];
+        self.from
+            .validate(&BTreeSet::new(), env, &join(&root, "from"), &mut problems);
+
+        let pattern = self.from.nodes.iter().map(|node| node.id).collect();
+        let scope = Scope {
+            pattern: &pattern,
+            env,
+            base_node: false,
+            base_values: false,
+        };
+        if let Some(guard) = &self.when {
+            scope.check(guard, true, &join(&root, "when"), &mut problems);
+        }
+        for (index, procedure) in self.to.iter().enumerate() {
+            procedure.validate(&scope, &join(&join(&root, "to"), index), &mut problems);
+        }
+        for (alt_index, alternative) in self.alternatives.iter().enumerate() {
+            let path = join(&join(&root, "alternatives"), alt_index);
+            for (index, procedure) in alternative.to.iter().enumerate() {
+                procedure.validate(&scope, &join(&join(&path, "to"), index), &mut problems);
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(yaml: &str) -> Vec<String> {
+        let env = Environment {
+            generation: 0,
+            parameters: maplit::btreemap! {"growth".to_string() => 1.5},
+        };
+        Rule::from_yaml(yaml)
+            .unwrap()
+            .validate(&env)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_rule() {
+        let found = problems(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}, {id: 1}]
+  edges: [[0, 1]]
+  not:
+    - nodes: [{id: 2, name: leaf}]
+      edges: [[0, 2]]
+when: n0.len > growth && generation < 10
+to:
+  - replace: {target: 1, with: {name: stem, values: {len: len * growth + degree}}}
+  - add:
+      neighbors: [{id: 0, label: branch, values: {angle: "rand(0, 90) + n1.len"}}]
+      node: {name: leaf, values: {size: "n0.len / depth"}}
+  - set_edge: {from: 0, to: 1, values: {angle: angle * 2}}
+  - merge: [1, 0, 1]
+"#,
+        );
+        assert!(found.is_empty(), "{:?}", found);
+    }
+
+    #[test]
+    fn test_problems() {
+        let found = problems(
+            r#"
+from:
+  nodes: [{id: 0, name: stem}, {id: 1, values: {len: [gt, {param: growth}]}}, {id: 0}]
+  edges: [[0, 3]]
+  not:
+    - nodes: [{id: 2, values: {len: [range, 0, {param: max_len}]}}]
+      edges: [[2, 4]]
+when: n2.len > 1
+to:
+  - delete: 5
+  - connect: {from: 0, to: 1, values: {angle: "angle + 1"}}
+alternatives:
+  - weight: 1
+    to:
+      - merge: [2, 0, 1]
+      - add: {neighbors: [0], node: {name: leaf, values: {size: "len * (2"}}}
+"#,
+        );
+        assert_eq!(
+            found,
+            vec![
+                "from.nodes.2: Node id 0 is used more than once",
+                "from.edges.0: Edge (0, 3) refers to node 3, which is not in the pattern",
+                "from.not.0.nodes.0.values.len: Condition refers to undefined parameter max_len",
+                "from.not.0.edges.0: Edge (2, 4) refers to node 4, which is not in the pattern",
+                "when: Expression \"n2.len > 1\" refers to n2.len, but there is no node 2 in the pattern",
+                "to.0.delete: Node 5 is not in the pattern",
+                "to.1.connect.values.angle: Expression \"angle + 1\" refers to undefined variable angle",
+                "alternatives.0.to.0.merge: The final node 2 is not one of the merged nodes",
+                "alternatives.0.to.1.add.node.values.size: Invalid expression \"len * (2\": Parse error: Missing 1 right parenthesis.",
+            ]
+        );
+    }
+}
@@ -1,89 +1,177 @@
-use std::fmt::{Debug, Formatter};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 pub enum RGGType {
     Int,
     Float,
+    Bool,
+    String,
+    Vec3,
 }
 
-#[derive(Clone)]
-pub struct Value {
-    pub(super) raw_value: i32,
-    pub(super) rgg_type: RGGType,
+/// A value stored in a node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    String(String),
+    Vec3([f32; 3]),
 }
 
-impl Debug for Value {
+/// Returned when a value is accessed as a type that it is not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeMismatch {
+    pub expected: RGGType,
+    pub found: RGGType,
+}
+
+impl Display for TypeMismatch {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Value")
-            .field("raw_value", &self.raw_value)
-            .field("as float", &self.get::<f32>())
-            .field("rgg_type", &self.rgg_type)
-            .finish()
+        write!(
+            f,
+            "Expected a value of type {:?} but found {:?}",
+            self.expected, self.found
+        )
     }
 }
 
+impl std::error::Error for TypeMismatch {}
+
 impl Value {
+    /// Create the zero value of the given type.
     pub fn new(value_type: RGGType) -> Self {
-        let pointer = match value_type {
-            RGGType::Int => Box::into_raw(Box::new(0)),
-            RGGType::Float => Box::into_raw(Box::new(0f32)) as *mut i32,
-        };
-        Self {
-            raw_value: unsafe { *pointer },
-            rgg_type: value_type,
+        match value_type {
+            RGGType::Int => Value::Int(0),
+            RGGType::Float => Value::Float(0.0),
+            RGGType::Bool => Value::Bool(false),
+            RGGType::String => Value::String(String::new()),
+            RGGType::Vec3 => Value::Vec3([0.0; 3]),
         }
     }
 
     pub fn new_int(i: i32) -> Self {
-        Self {
-            raw_value: i,
-            rgg_type: RGGType::Int,
-        }
+        Value::Int(i)
     }
 
     pub fn new_float(f: f32) -> Self {
-        let p = Box::into_raw(Box::new(f)) as *mut i32;
-        Self {
-            raw_value: unsafe { *p },
-            rgg_type: RGGType::Float,
+        Value::Float(f)
+    }
+
+    pub fn rgg_type(&self) -> RGGType {
+        match self {
+            Value::Int(_) => RGGType::Int,
+            Value::Float(_) => RGGType::Float,
+            Value::Bool(_) => RGGType::Bool,
+            Value::String(_) => RGGType::String,
+            Value::Vec3(_) => RGGType::Vec3,
+        }
+    }
+
+    fn mismatch(&self, expected: RGGType) -> TypeMismatch {
+        TypeMismatch {
+            expected,
+            found: self.rgg_type(),
+        }
+    }
+
+    pub fn as_int(&self) -> Result<i32, TypeMismatch> {
+        match self {
+            Value::Int(i) => Ok(*i),
+            _ => Err(self.mismatch(RGGType::Int)),
         }
     }
 
-    pub fn get<T: Copy>(&self) -> T {
-        unsafe { *(&self.raw_value as *const i32 as *const T) }
+    pub fn as_float(&self) -> Result<f32, TypeMismatch> {
+        match self {
+            Value::Float(f) => Ok(*f),
+            _ => Err(self.mismatch(RGGType::Float)),
+        }
     }
 
-    pub fn get_mut<T: Copy>(&mut self) -> &mut T {
-        unsafe { &mut *(self.raw_value as *mut T) }
+    pub fn as_bool(&self) -> Result<bool, TypeMismatch> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(self.mismatch(RGGType::Bool)),
+        }
     }
 
-    pub fn set_f32(&mut self, v: f32) {
-        let p = Box::into_raw(Box::new(v)) as *const i32;
-        unsafe {
-            self.raw_value = *p;
+    pub fn as_str(&self) -> Result<&str, TypeMismatch> {
+        match self {
+            Value::String(s) => Ok(s.as_str()),
+            _ => Err(self.mismatch(RGGType::String)),
         }
     }
 
-    pub fn set_i32(&mut self, v: i32) {
-        self.raw_value = v;
+    pub fn as_vec3(&self) -> Result<[f32; 3], TypeMismatch> {
+        match self {
+            Value::Vec3(v) => Ok(*v),
+            _ => Err(self.mismatch(RGGType::Vec3)),
+        }
     }
-}
 
-impl PartialEq for Value {
-    fn eq(&self, other: &Self) -> bool {
-        self.rgg_type == other.rgg_type && self.raw_value == other.raw_value
+    /// Get either an int or a float as a float.
+    pub fn as_number(&self) -> Result<f64, TypeMismatch> {
+        match self {
+            Value::Int(i) => Ok(*i as f64),
+            Value::Float(f) => Ok(*f as f64),
+            _ => Err(self.mismatch(RGGType::Float)),
+        }
+    }
+
+    /// Compare two values. Ints and floats are compared numerically with each other.
+    /// Returns None if the values cannot be compared.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(l), Value::Int(r)) => Some(l.cmp(r)),
+            (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+            (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+            (Value::Vec3(l), Value::Vec3(r)) => {
+                if l == r {
+                    Some(Ordering::Equal)
+                } else {
+                    None
+                }
+            }
+            (l, r) => l.as_number().ok()?.partial_cmp(&r.as_number().ok()?),
+        }
     }
 }
 
 impl From<f32> for Value {
     fn from(f: f32) -> Self {
-        Value::new_float(f)
+        Value::Float(f)
     }
 }
 
 impl From<i32> for Value {
     fn from(i: i32) -> Self {
-        Value::new_int(i)
+        Value::Int(i)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<[f32; 3]> for Value {
+    fn from(v: [f32; 3]) -> Self {
+        Value::Vec3(v)
     }
 }
 
@@ -94,26 +182,47 @@ mod tests {
     #[test]
     fn test_get_i32() {
         let v = Value::new(RGGType::Int);
-        assert_eq!(v.get::<i32>(), 0);
+        assert_eq!(v.as_int(), Ok(0));
     }
 
     #[test]
     fn test_get_f32() {
         let v = Value::new(RGGType::Float);
-        assert_eq!(v.get::<f32>(), 0f32);
+        assert_eq!(v.as_float(), Ok(0f32));
     }
 
     #[test]
-    fn test_set_i32() {
-        let mut v = Value::new(RGGType::Int);
-        v.set_i32(3142);
-        assert_eq!(v.get::<i32>(), 3142);
+    fn test_get_mismatch() {
+        let v = Value::new_int(3142);
+        assert_eq!(
+            v.as_float(),
+            Err(TypeMismatch {
+                expected: RGGType::Float,
+                found: RGGType::Int
+            })
+        );
+        assert!(Value::from("hi").as_number().is_err());
+        assert_eq!(v.as_number(), Ok(3142.0));
     }
 
     #[test]
-    fn test_set_f32() {
-        let mut v = Value::new(RGGType::Float);
-        v.set_f32(3142.1);
-        assert_eq!(v.get::<f32>(), 3142.1);
+    fn test_compare() {
+        assert_eq!(
+            Value::new_int(1).compare(&Value::new_float(1.5)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            Value::new_float(2.0).compare(&Value::new_int(2)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            Value::from("b").compare(&Value::from("a")),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(Value::from(true).compare(&Value::new_int(1)), None);
+        assert_eq!(
+            Value::from([0.0, 1.0, 2.0]).compare(&Value::from([0.0; 3])),
+            None
+        );
     }
 }